@@ -0,0 +1,301 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::db::{get_all_kanji, learned_kanji};
+use crate::epub::read_epub;
+use crate::kanji::{is_kanji, Knowledge};
+use crate::subtitles::{parse_subtitles, SubtitleFormat};
+use crate::CliError;
+
+/// Read a text file as UTF-8, UTF-16 with a byte order mark, or Shift_JIS,
+/// which older Japanese text files still often use
+pub fn read_text_file(path: &Path) -> Result<String, CliError> {
+    let bytes = fs::read(path)?;
+    if let Some((encoding, _)) = encoding_rs::Encoding::for_bom(&bytes) {
+        let (text, _, _) = encoding.decode(&bytes);
+        return Ok(text.into_owned());
+    }
+    match String::from_utf8(bytes) {
+        Ok(text) => Ok(text),
+        Err(err) => {
+            let (text, _, had_errors) = encoding_rs::SHIFT_JIS.decode(err.as_bytes());
+            if had_errors {
+                return Err(CliError::Custom(format!(
+                    "{} is neither UTF-8, UTF-16 nor Shift_JIS",
+                    path.display()
+                )));
+            }
+            Ok(text.into_owned())
+        }
+    }
+}
+
+/// Read the text of a file to analyze, keeping only the dialogue of
+/// subtitle files and the chapter text of EPUBs
+pub fn read_document(path: &Path) -> Result<String, CliError> {
+    if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("epub"))
+    {
+        let chapters: Vec<String> = read_epub(path)?
+            .into_iter()
+            .map(|chapter| chapter.text)
+            .collect();
+        return Ok(chapters.join("\n"));
+    }
+    let text = read_text_file(path)?;
+    Ok(match SubtitleFormat::from_path(path) {
+        Some(format) => parse_subtitles(format, &text).join("\n"),
+        None => text,
+    })
+}
+
+/// The level of every kanji in the kanji table. Kanji promoted to Seen by
+/// exposure alone haven't been learned (see `learned_kanji`), so they count
+/// as None here too, as they do for `next`.
+pub fn kanji_levels() -> Result<HashMap<char, Knowledge>, CliError> {
+    let learned = learned_kanji()?;
+    Ok(get_all_kanji()?
+        .into_iter()
+        .filter_map(|entry| {
+            let kanji = entry.kanji.chars().next()?;
+            let level = match entry.level {
+                Knowledge::Seen if !learned.contains(&kanji) => Knowledge::None,
+                level => level,
+            };
+            Some((kanji, level))
+        })
+        .collect())
+}
+
+/// How often each kanji occurs in a text
+#[derive(Debug, Default, Clone)]
+pub struct KanjiCounts {
+    counts: HashMap<char, usize>,
+}
+
+impl KanjiCounts {
+    pub fn from_text(text: &str) -> KanjiCounts {
+        let mut counts = KanjiCounts::default();
+        counts.add_text(text);
+        counts
+    }
+
+    pub fn add_text(&mut self, text: &str) {
+        for kanji in text.chars().filter(|c| is_kanji(*c)) {
+            *self.counts.entry(kanji).or_default() += 1;
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (char, usize)> + '_ {
+        self.counts.iter().map(|(kanji, count)| (*kanji, *count))
+    }
+
+    /// Score the text against known kanji levels. Kanji missing from the
+    /// table count as Knowledge::None.
+    pub fn coverage(&self, levels: &HashMap<char, Knowledge>) -> Coverage {
+        let mut coverage = Coverage {
+            tokens: self.counts.values().sum(),
+            unique: self.counts.len(),
+            ..Default::default()
+        };
+        let mut by_level: HashMap<Knowledge, LevelShare> = HashMap::new();
+        let mut understood = 0.0;
+        for (kanji, count) in self.iter() {
+            let level = levels.get(&kanji).copied().unwrap_or_default();
+            let share = by_level.entry(level).or_insert(LevelShare {
+                level,
+                tokens: 0,
+                unique: 0,
+            });
+            share.tokens += count;
+            share.unique += 1;
+            understood += comprehension_weight(level) * count as f64;
+            if level == Knowledge::None {
+                coverage.unknown.push((kanji, count));
+            }
+        }
+        coverage.levels = (0..=5)
+            .filter_map(Knowledge::from_level)
+            .map(|level| {
+                by_level.remove(&level).unwrap_or(LevelShare {
+                    level,
+                    tokens: 0,
+                    unique: 0,
+                })
+            })
+            .collect();
+        coverage
+            .unknown
+            .sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        if coverage.tokens > 0 {
+            coverage.comprehension = understood / coverage.tokens as f64;
+        }
+        coverage
+    }
+}
+
+/// How much of a kanji's meaning a reader gets at each level. Seen kanji,
+/// met in Anki vocabulary or marked by hand, count for half.
+fn comprehension_weight(level: Knowledge) -> f64 {
+    match level {
+        Knowledge::None => 0.0,
+        Knowledge::Seen => 0.5,
+        _ => 1.0,
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct LevelShare {
+    pub level: Knowledge,
+    /// Occurrences of kanji at this level
+    pub tokens: usize,
+    /// Distinct kanji at this level
+    pub unique: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Coverage {
+    pub tokens: usize,
+    pub unique: usize,
+    /// Every level from None to Master, in order
+    pub levels: Vec<LevelShare>,
+    /// Unknown kanji with their counts, most frequent first
+    pub unknown: Vec<(char, usize)>,
+    /// Estimated share of kanji occurrences the reader understands, 0 to 1
+    pub comprehension: f64,
+}
+
+impl Coverage {
+    /// Share of kanji occurrences at Knowledge::None, 0 to 1
+    pub fn unknown_share(&self) -> f64 {
+        if self.tokens == 0 {
+            return 0.0;
+        }
+        let unknown: usize = self.unknown.iter().map(|(_, count)| count).sum();
+        unknown as f64 / self.tokens as f64
+    }
+
+    pub fn verdict(&self) -> &'static str {
+        match self.comprehension {
+            c if c >= 0.98 => "comfortable",
+            c if c >= 0.9 => "within reach",
+            c if c >= 0.75 => "challenging",
+            _ => "out of reach for now",
+        }
+    }
+}
+
+/// New kanji in one part of a longer text, such as a chapter
+#[derive(Debug)]
+pub struct PartDensity {
+    /// Unknown kanji not met in an earlier part, with their counts here,
+    /// most frequent first
+    pub new: Vec<(char, usize)>,
+    pub coverage: Coverage,
+}
+
+impl PartDensity {
+    /// New kanji per thousand kanji occurrences
+    pub fn per_thousand(&self) -> f64 {
+        if self.coverage.tokens == 0 {
+            return 0.0;
+        }
+        self.new.len() as f64 * 1000.0 / self.coverage.tokens as f64
+    }
+}
+
+/// Where a text introduces kanji the reader doesn't know, part by part. The
+/// first `read` parts are taken as already read: their kanji count as met
+/// and they are left out of the result.
+pub fn new_kanji_density(
+    parts: &[&str],
+    levels: &HashMap<char, Knowledge>,
+    read: usize,
+) -> Vec<PartDensity> {
+    let mut met: HashSet<char> = HashSet::new();
+    let mut densities = vec![];
+    for (i, part) in parts.iter().enumerate() {
+        let counts = KanjiCounts::from_text(part);
+        if i >= read {
+            let coverage = counts.coverage(levels);
+            let new = coverage
+                .unknown
+                .iter()
+                .filter(|(kanji, _)| !met.contains(kanji))
+                .copied()
+                .collect();
+            densities.push(PartDensity { new, coverage });
+        }
+        met.extend(counts.iter().map(|(kanji, _)| kanji));
+    }
+    densities
+}
+
+/// An unknown kanji worth learning next
+#[derive(Debug, Clone, Serialize)]
+pub struct Recommendation {
+    pub kanji: char,
+    /// Times met in immersion text
+    pub count: u32,
+    /// Share of all kanji met that learning it would cover, 0 to 1
+    pub gain: f64,
+    pub components: Vec<char>,
+    /// Components that are known kanji or appear in a known kanji
+    pub known_components: Vec<char>,
+    pub score: f64,
+}
+
+/// Rank the kanji met in immersion text that haven't been learned yet.
+/// Frequency counts most, and each kanji gets up to twice the weight when all
+/// its components are already familiar, since those are quicker to learn.
+/// Kanji in `exclude` are skipped.
+pub fn recommend(
+    exposure: &[(char, u32)],
+    known: &HashSet<char>,
+    components: &HashMap<char, Vec<char>>,
+    exclude: &HashSet<char>,
+) -> Vec<Recommendation> {
+    let total: u32 = exposure.iter().map(|(_, count)| count).sum();
+    let familiar: HashSet<char> = known
+        .iter()
+        .flat_map(|kanji| components.get(kanji).into_iter().flatten())
+        .chain(known)
+        .copied()
+        .collect();
+    let mut recommendations: Vec<Recommendation> = exposure
+        .iter()
+        .filter(|(kanji, _)| !known.contains(kanji) && !exclude.contains(kanji))
+        .map(|(kanji, count)| {
+            let parts = components.get(kanji).cloned().unwrap_or_default();
+            let known_components: Vec<char> = parts
+                .iter()
+                .filter(|part| familiar.contains(part))
+                .copied()
+                .collect();
+            let known_share = if parts.is_empty() {
+                0.0
+            } else {
+                known_components.len() as f64 / parts.len() as f64
+            };
+            Recommendation {
+                kanji: *kanji,
+                count: *count,
+                gain: *count as f64 / total as f64,
+                components: parts,
+                known_components,
+                score: *count as f64 * (1.0 + known_share),
+            }
+        })
+        .collect();
+    recommendations.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then(b.count.cmp(&a.count))
+            .then(a.kanji.cmp(&b.kanji))
+    });
+    recommendations
+}
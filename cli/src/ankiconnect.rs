@@ -1,98 +1,302 @@
+use crate::config::{read_config, AnkiConnectConfig};
 use crate::CliError;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::Value;
 use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::Duration;
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct GetDecksResult {
-    pub result: Vec<String>,
-}
-
-pub async fn get_decks() -> Result<GetDecksResult, CliError> {
-    let client = reqwest::Client::new();
-    let resp = client
-        .post("http://localhost:8765")
-        .body(
-            r#"{
-        "action": "deckNames",
-        "version": 6
-    }"#,
-        )
-        .send()
-        .await?
-        .json::<GetDecksResult>()
-        .await?;
-    Ok(resp)
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-pub struct GetCardsResult {
-    pub result: Vec<u64>,
-    pub err: Option<String>,
-}
-
-pub async fn get_cards(deck_name: String) -> Result<GetCardsResult, CliError> {
-    let client = reqwest::Client::new();
-    let body = format!(
-        r#"{{
-        "action": "findCards",
-        "version": 6,
-        "params": {{
-            "query": "deck:\"{}\""
-        }}
-    }}"#,
-        deck_name
-    );
-    let resp = client
-        .post("http://localhost:8765")
-        .body(body)
-        .send()
-        .await?
-        .json::<GetCardsResult>()
-        .await?;
-    if resp.err.is_some() {
-        return Err(CliError::Custom(resp.err.unwrap()));
+/// AnkiConnect API version every request is made against
+const VERSION: u8 = 6;
+
+/// Card ids sent per cardsInfo action when fetching card content
+const CARDS_PER_ACTION: usize = 500;
+
+/// An AnkiConnect action, serialised as its `params`
+pub trait Action: Serialize {
+    const NAME: &'static str;
+    type Output: DeserializeOwned;
+}
+
+#[derive(Serialize)]
+struct Request<'a, P: Serialize> {
+    action: &'a str,
+    version: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    params: Option<P>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key: Option<&'a str>,
+}
+
+/// Every AnkiConnect reply has this shape, with exactly one of the two set
+#[derive(Deserialize, Debug)]
+struct Response<T> {
+    result: Option<T>,
+    error: Option<String>,
+}
+
+impl<T: DeserializeOwned> Response<T> {
+    fn into_result(self) -> Result<T, CliError> {
+        match self.error {
+            Some(error) if error.to_lowercase().contains("api key") => {
+                Err(CliError::AnkiKeyRejected)
+            }
+            Some(error) => Err(CliError::AnkiConnect(error)),
+            None => match self.result {
+                Some(result) => Ok(result),
+                // Actions with no result (such as sync) reply with null
+                None => Ok(serde_json::from_value(Value::Null)?),
+            },
+        }
     }
-    Ok(resp)
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct GetCardsContentResult {
-    pub result: Vec<CardContent>,
-    pub err: Option<String>,
+#[derive(Serialize)]
+struct MultiAction<'a, A: Action> {
+    action: &'static str,
+    version: u8,
+    params: &'a A,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize)]
+struct Multi<'a, A: Action> {
+    actions: Vec<MultiAction<'a, A>>,
+}
+
+/// HTTP client and settings shared by every AnkiConnect request
+pub struct AnkiConnect {
+    client: reqwest::Client,
+    config: AnkiConnectConfig,
+}
+
+static ANKICONNECT: OnceLock<AnkiConnect> = OnceLock::new();
+
+impl AnkiConnect {
+    pub fn new(config: AnkiConnectConfig) -> Result<AnkiConnect, CliError> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()?;
+        Ok(AnkiConnect { client, config })
+    }
+
+    /// The client configured from config.json, created on first use
+    pub fn shared() -> Result<&'static AnkiConnect, CliError> {
+        if let Some(anki) = ANKICONNECT.get() {
+            return Ok(anki);
+        }
+        let anki = AnkiConnect::new(read_config()?.ankiconnect)?;
+        Ok(ANKICONNECT.get_or_init(|| anki))
+    }
+
+    /// Run a single action
+    pub async fn invoke<A: Action>(&self, action: &A) -> Result<A::Output, CliError> {
+        self.post::<_, A::Output>(A::NAME, action)
+            .await?
+            .into_result()
+    }
+
+    /// Run several actions of the same kind in one round trip with `multi`.
+    /// Fails on the first action that returned an error.
+    pub async fn invoke_multi<A: Action>(&self, actions: &[A]) -> Result<Vec<A::Output>, CliError> {
+        let multi = Multi {
+            actions: actions
+                .iter()
+                .map(|params| MultiAction {
+                    action: A::NAME,
+                    version: VERSION,
+                    params,
+                })
+                .collect(),
+        };
+        let responses: Vec<Response<A::Output>> = self
+            .post::<_, Vec<Response<A::Output>>>("multi", &multi)
+            .await?
+            .into_result()?;
+        responses.into_iter().map(Response::into_result).collect()
+    }
+
+    /// Send a request, retrying when Anki can't be reached
+    async fn post<P: Serialize, T: DeserializeOwned>(
+        &self,
+        action: &str,
+        params: &P,
+    ) -> Result<Response<T>, CliError> {
+        let params = serde_json::to_value(params)?;
+        let request = Request {
+            action,
+            version: VERSION,
+            // Unit structs serialise to null, which AnkiConnect rejects as params
+            params: (!params.is_null()).then_some(params),
+            key: self.config.api_key.as_deref(),
+        };
+
+        let mut attempt = 0;
+        loop {
+            let sent = self
+                .client
+                .post(&self.config.url)
+                .json(&request)
+                .send()
+                .await;
+            match sent {
+                Ok(resp) => return Ok(resp.json().await?),
+                Err(err)
+                    if (err.is_connect() || err.is_timeout()) && attempt < self.config.retries =>
+                {
+                    attempt += 1;
+                    tokio::time::sleep(Duration::from_millis(self.config.retry_delay_ms)).await;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+/// Quote a deck name for use in a search query, escaping the characters
+/// Anki's search syntax treats specially
+pub fn deck_query(deck_name: &str) -> String {
+    let mut query = String::from("deck:\"");
+    for c in deck_name.chars() {
+        if matches!(c, '\\' | '"' | '*' | '_') {
+            query.push('\\');
+        }
+        query.push(c);
+    }
+    query.push('"');
+    query
+}
+
+#[derive(Serialize)]
+pub struct DeckNames;
+
+impl Action for DeckNames {
+    const NAME: &'static str = "deckNames";
+    type Output = Vec<String>;
+}
+
+#[derive(Serialize)]
+pub struct FindCards {
+    pub query: String,
+}
+
+impl Action for FindCards {
+    const NAME: &'static str = "findCards";
+    type Output = Vec<u64>;
+}
+
+#[derive(Serialize)]
+pub struct CardsInfo {
+    pub cards: Vec<u64>,
+}
+
+impl Action for CardsInfo {
+    const NAME: &'static str = "cardsInfo";
+    type Output = Vec<CardContent>;
+}
+
+#[derive(Serialize)]
+pub struct GetReviewsOfCards {
+    pub cards: Vec<String>,
+}
+
+impl Action for GetReviewsOfCards {
+    const NAME: &'static str = "getReviewsOfCards";
+    /// Reviews keyed by card id
+    type Output = HashMap<String, Vec<AnkiReview>>;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CardContent {
+    #[serde(rename = "cardId", default)]
+    pub card_id: u64,
+    /// Id of the note the card belongs to
+    #[serde(default)]
+    pub note: u64,
     pub interval: u16,
+    /// Name of the note type
+    #[serde(rename = "modelName", default)]
+    pub model_name: String,
+    /// Last modification time of the card, in seconds
+    #[serde(rename = "mod", default)]
+    pub modified: i64,
     pub fields: HashMap<String, Field>,
+    /// Review history, oldest first. Filled by `get_reviews` or the collection reader.
+    #[serde(skip)]
+    pub reviews: Vec<AnkiReview>,
+}
+
+/// One entry of Anki's revlog
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AnkiReview {
+    /// Review time in milliseconds since the epoch
+    pub id: i64,
+    /// Answer button pressed, 1-4. Manual reschedules have 0.
+    pub ease: u8,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Field {
     pub value: String,
     pub order: u8,
 }
-pub async fn get_card_content(ids: Vec<u64>) -> Result<GetCardsContentResult, CliError> {
-    let client = reqwest::Client::new();
-    let resp = client
-        .post("http://localhost:8765")
-        .body(
-            json!({
-                "action": "cardsInfo",
-                "version": 6,
-                "params": {
-                    "cards": ids
-                }
-            })
-            .to_string(),
-        )
-        .send()
-        .await?
-        .json::<GetCardsContentResult>()
-        .await?;
-    if resp.err.is_some() {
-        return Err(CliError::Custom(resp.err.unwrap()));
+
+pub async fn get_decks() -> Result<Vec<String>, CliError> {
+    AnkiConnect::shared()?.invoke(&DeckNames).await
+}
+
+pub async fn get_cards(deck_name: &str) -> Result<Vec<u64>, CliError> {
+    find_cards(deck_query(deck_name)).await
+}
+
+pub async fn find_cards(query: String) -> Result<Vec<u64>, CliError> {
+    AnkiConnect::shared()?.invoke(&FindCards { query }).await
+}
+
+/// Content and review history of the given cards
+pub async fn get_cards_with_reviews(ids: &[u64]) -> Result<Vec<CardContent>, CliError> {
+    let mut cards = get_card_content(ids).await?;
+    let mut reviews = get_reviews(ids).await?;
+    for card in &mut cards {
+        if let Some(history) = reviews.remove(&card.card_id) {
+            card.reviews = history;
+        }
+    }
+    Ok(cards)
+}
+
+/// Review history of each card, batched like `get_card_content`
+pub async fn get_reviews(ids: &[u64]) -> Result<HashMap<u64, Vec<AnkiReview>>, CliError> {
+    let actions: Vec<GetReviewsOfCards> = ids
+        .chunks(CARDS_PER_ACTION)
+        .map(|chunk| GetReviewsOfCards {
+            cards: chunk.iter().map(|id| id.to_string()).collect(),
+        })
+        .collect();
+    if actions.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let batches = AnkiConnect::shared()?.invoke_multi(&actions).await?;
+    let mut reviews = HashMap::new();
+    for (id, mut history) in batches.into_iter().flatten() {
+        let Ok(id) = id.parse() else { continue };
+        history.sort_by_key(|review| review.id);
+        reviews.insert(id, history);
+    }
+    Ok(reviews)
+}
+
+/// Fetch card content, split across batched cardsInfo actions for large decks
+pub async fn get_card_content(ids: &[u64]) -> Result<Vec<CardContent>, CliError> {
+    let actions: Vec<CardsInfo> = ids
+        .chunks(CARDS_PER_ACTION)
+        .map(|chunk| CardsInfo {
+            cards: chunk.to_vec(),
+        })
+        .collect();
+    if actions.is_empty() {
+        return Ok(vec![]);
     }
-    Ok(resp)
+    let batches = AnkiConnect::shared()?.invoke_multi(&actions).await?;
+    Ok(batches.into_iter().flatten().collect())
 }
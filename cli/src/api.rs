@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+
+use rocket::http::Status;
+use rocket::response::{self, Responder};
+use rocket::serde::json::Json;
+use rocket::{delete, get, post, put, routes, Request, Route};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{add_deck, read_config, remove_deck, update_deck, Config, DeckConfig};
+use crate::db::{
+    due_cards, get_all_kanji, get_kanji, review_logs_for, set_kanji_level, KanjiReviewLog, KanjiSrs,
+};
+use crate::kanji::{is_kanji, KanjiEntry, Knowledge};
+use crate::sync::{sync_deck, SyncReport};
+use crate::CliError;
+
+/// Routes served under /api/v1
+pub fn routes() -> Vec<Route> {
+    routes![
+        list_kanji,
+        kanji,
+        update_kanji,
+        kanji_reviews,
+        list_due_cards,
+        config,
+        list_decks,
+        create_deck,
+        update_deck_config,
+        delete_deck,
+        sync,
+    ]
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    status: Status,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+impl ApiError {
+    fn new(status: Status, message: impl Into<String>) -> ApiError {
+        ApiError {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<CliError> for ApiError {
+    fn from(err: CliError) -> Self {
+        match err {
+            CliError::SQL(rusqlite::Error::QueryReturnedNoRows) => {
+                ApiError::new(Status::NotFound, "Not found")
+            }
+            CliError::Custom(message) => ApiError::new(Status::InternalServerError, message),
+            err => ApiError::new(Status::InternalServerError, format!("{:?}", err)),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        (
+            self.status,
+            Json(ErrorBody {
+                error: self.message,
+            }),
+        )
+            .respond_to(req)
+    }
+}
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+/// Run database or config file work on a blocking thread, so a slow query or
+/// sync doesn't hold up Rocket's async workers
+async fn blocking<T, F>(work: F) -> Result<T, ApiError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, CliError> + Send + 'static,
+{
+    match tokio::task::spawn_blocking(work).await {
+        Ok(result) => Ok(result?),
+        Err(err) => Err(ApiError::new(Status::InternalServerError, err.to_string())),
+    }
+}
+
+fn parse_kanji(kanji: &str) -> Result<char, ApiError> {
+    let mut chars = kanji.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if is_kanji(c) => Ok(c),
+        _ => Err(ApiError::new(
+            Status::BadRequest,
+            format!("Expected a single kanji, got {:?}", kanji),
+        )),
+    }
+}
+
+#[get("/kanji?<min_level>&<max_level>")]
+async fn list_kanji(
+    min_level: Option<Knowledge>,
+    max_level: Option<Knowledge>,
+) -> ApiResult<Vec<KanjiEntry>> {
+    let kanji = blocking(get_all_kanji)
+        .await?
+        .into_iter()
+        .filter(|entry| min_level.map_or(true, |min| entry.level >= min))
+        .filter(|entry| max_level.map_or(true, |max| entry.level <= max))
+        .collect();
+    Ok(Json(kanji))
+}
+
+#[get("/kanji/<kanji>")]
+async fn kanji(kanji: &str) -> ApiResult<KanjiEntry> {
+    let kanji = parse_kanji(kanji)?;
+    Ok(Json(blocking(move || get_kanji(kanji)).await?))
+}
+
+#[derive(Deserialize)]
+struct LevelUpdate {
+    level: Knowledge,
+}
+
+#[put("/kanji/<kanji>", data = "<update>")]
+async fn update_kanji(kanji: &str, update: Json<LevelUpdate>) -> ApiResult<KanjiEntry> {
+    let kanji = parse_kanji(kanji)?;
+    let level = update.level;
+    let entry = blocking(move || {
+        set_kanji_level(kanji, level)?;
+        get_kanji(kanji)
+    })
+    .await?;
+    Ok(Json(entry))
+}
+
+#[get("/kanji/<kanji>/reviews")]
+async fn kanji_reviews(kanji: &str) -> ApiResult<Vec<KanjiReviewLog>> {
+    let kanji = parse_kanji(kanji)?;
+    Ok(Json(blocking(move || review_logs_for(kanji)).await?))
+}
+
+#[get("/cards/due")]
+async fn list_due_cards() -> ApiResult<Vec<KanjiSrs>> {
+    Ok(Json(blocking(due_cards).await?))
+}
+
+#[get("/config")]
+async fn config() -> ApiResult<Config> {
+    Ok(Json(blocking(read_config).await?))
+}
+
+#[get("/config/decks")]
+async fn list_decks() -> ApiResult<Vec<DeckConfig>> {
+    Ok(Json(blocking(read_config).await?.decks))
+}
+
+#[post("/config/decks", data = "<deck>")]
+async fn create_deck(deck: Json<DeckConfig>) -> Result<(Status, Json<DeckConfig>), ApiError> {
+    let config = blocking(read_config).await?;
+    if config.decks.iter().any(|d| d.name == deck.name) {
+        return Err(ApiError::new(
+            Status::Conflict,
+            format!("Deck {} is already configured", deck.name),
+        ));
+    }
+    let deck = deck.into_inner();
+    let added = deck.clone();
+    blocking(move || add_deck(added)).await?;
+    Ok((Status::Created, Json(deck)))
+}
+
+#[derive(Deserialize)]
+struct DeckUpdate {
+    word_field: Option<String>,
+    /// Replaces every per-note-type mapping when given
+    note_fields: Option<BTreeMap<String, String>>,
+}
+
+#[put("/config/decks/<name>", data = "<update>")]
+async fn update_deck_config(name: &str, update: Json<DeckUpdate>) -> ApiResult<DeckConfig> {
+    let update = update.into_inner();
+    let deck_name = name.to_string();
+    let deck = blocking(move || {
+        update_deck(&deck_name, |deck| {
+            if let Some(word_field) = update.word_field {
+                deck.word_field = word_field;
+            }
+            if let Some(note_fields) = update.note_fields {
+                deck.note_fields = note_fields;
+            }
+        })
+    })
+    .await?;
+    match deck {
+        Some(deck) => Ok(Json(deck)),
+        None => Err(ApiError::new(
+            Status::NotFound,
+            format!("No deck named {}", name),
+        )),
+    }
+}
+
+#[delete("/config/decks/<name>")]
+async fn delete_deck(name: &str) -> Result<Status, ApiError> {
+    let deck_name = name.to_string();
+    if !blocking(move || remove_deck(&deck_name)).await? {
+        return Err(ApiError::new(
+            Status::NotFound,
+            format!("No deck named {}", name),
+        ));
+    }
+    Ok(Status::NoContent)
+}
+
+/// Sync one configured deck, or every configured deck when none is given.
+/// The whole sync runs on a blocking thread, driving the AnkiConnect
+/// requests from there, as it writes SQLite between them.
+#[post("/sync?<deck>")]
+async fn sync(deck: Option<&str>) -> ApiResult<Vec<SyncReport>> {
+    let decks: Vec<DeckConfig> = blocking(read_config)
+        .await?
+        .decks
+        .into_iter()
+        .filter(|d| deck.map_or(true, |name| d.name == name))
+        .collect();
+    if let (Some(name), true) = (deck, decks.is_empty()) {
+        return Err(ApiError::new(
+            Status::NotFound,
+            format!("No deck named {}", name),
+        ));
+    }
+    let reports = blocking(move || {
+        let runtime = tokio::runtime::Handle::current();
+        let mut reports = vec![];
+        for deck in decks {
+            reports.push(runtime.block_on(sync_deck(&deck))?);
+        }
+        Ok(reports)
+    })
+    .await?;
+    Ok(Json(reports))
+}
@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use rusqlite::{Connection, OpenFlags};
+use serde::Deserialize;
+
+use crate::ankiconnect::{AnkiReview, CardContent, Field};
+use crate::CliError;
+
+/// Collection files a package may contain, newest format first. Packages
+/// exported by recent Anki versions also include a placeholder
+/// collection.anki2 for old clients, so the order matters.
+const COLLECTION_FILES: [&str; 3] = [
+    "collection.anki21b",
+    "collection.anki21",
+    "collection.anki2",
+];
+
+/// Separator between note fields, and between deck names in newer schemas
+const FIELD_SEPARATOR: char = '\u{1f}';
+
+/// Cards read from an Anki collection file, for syncing without AnkiConnect
+pub struct Collection {
+    decks: Vec<String>,
+    cards: Vec<(String, CardContent)>,
+}
+
+#[derive(Deserialize)]
+struct LegacyDeck {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct LegacyModel {
+    name: String,
+    flds: Vec<LegacyField>,
+}
+
+/// A note type's name and its field names with their positions
+#[derive(Default)]
+struct Model {
+    name: String,
+    fields: Vec<(String, u8)>,
+}
+
+#[derive(Deserialize)]
+struct LegacyField {
+    name: String,
+    ord: u8,
+}
+
+impl Collection {
+    /// Read an .apkg/.colpkg package or a bare collection.anki2 file
+    pub fn open(path: &Path) -> Result<Collection, CliError> {
+        let is_package = path
+            .extension()
+            .is_some_and(|ext| ext == "apkg" || ext == "colpkg");
+        if !is_package {
+            let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+            return Collection::read(&connection);
+        }
+
+        let bytes = extract_collection(path)?;
+        // SQLite needs a file to open, so stage the collection in the temp dir
+        let staged = staging_path();
+        fs::write(&staged, bytes)?;
+        let collection = Connection::open_with_flags(&staged, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(CliError::from)
+            .and_then(|connection| Collection::read(&connection));
+        fs::remove_file(&staged)?;
+        collection
+    }
+
+    /// Load every card of the collection with its deck name and note fields
+    pub fn read(connection: &Connection) -> Result<Collection, CliError> {
+        let (decks, models) = if has_table(connection, "fields")? {
+            (read_decks(connection)?, read_fields(connection)?)
+        } else {
+            read_legacy_col(connection)?
+        };
+
+        let mut reviews = read_reviews(connection)?;
+        let mut stmt = connection.prepare(
+            "SELECT cards.did, cards.ivl, notes.mid, notes.flds, cards.id,
+                MAX(cards.mod, notes.mod), cards.nid
+            FROM cards JOIN notes ON notes.id = cards.nid",
+        )?;
+        let mut rows = stmt.query([])?;
+        let mut cards = vec![];
+        while let Some(row) = rows.next()? {
+            let (Some(deck), Some(model)) = (
+                decks.get(&row.get::<_, i64>(0)?),
+                models.get(&row.get::<_, i64>(2)?),
+            ) else {
+                continue;
+            };
+            // Learning cards store a negative interval in seconds
+            let interval = row.get::<_, i64>(1)?.clamp(0, u16::MAX as i64) as u16;
+            let values: String = row.get(3)?;
+            let fields = values
+                .split(FIELD_SEPARATOR)
+                .enumerate()
+                .filter_map(|(ord, value)| {
+                    let (name, order) = model
+                        .fields
+                        .iter()
+                        .find(|(_, order)| *order as usize == ord)?;
+                    Some((
+                        name.clone(),
+                        Field {
+                            value: value.to_string(),
+                            order: *order,
+                        },
+                    ))
+                })
+                .collect();
+            let card_id: u64 = row.get(4)?;
+            cards.push((
+                deck.clone(),
+                CardContent {
+                    card_id,
+                    note: row.get(6)?,
+                    interval,
+                    model_name: model.name.clone(),
+                    modified: row.get(5)?,
+                    fields,
+                    reviews: reviews.remove(&card_id).unwrap_or_default(),
+                },
+            ));
+        }
+
+        let mut names: Vec<String> = decks.into_values().collect();
+        names.sort();
+        Ok(Collection {
+            decks: names,
+            cards,
+        })
+    }
+
+    pub fn decks(&self) -> Vec<String> {
+        self.decks.clone()
+    }
+
+    /// Cards in a deck or any of its subdecks, as AnkiConnect's `deck:` search returns them
+    pub fn deck_cards(&self, deck: &str) -> Vec<CardContent> {
+        let prefix = format!("{}::", deck);
+        self.cards
+            .iter()
+            .filter(|(name, _)| name == deck || name.starts_with(&prefix))
+            .map(|(_, card)| card.clone())
+            .collect()
+    }
+}
+
+fn extract_collection(path: &Path) -> Result<Vec<u8>, CliError> {
+    let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+    for name in COLLECTION_FILES {
+        let Ok(mut file) = archive.by_name(name) else {
+            continue;
+        };
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes)?;
+        if name.ends_with("b") {
+            bytes = zstd::decode_all(bytes.as_slice())?;
+        }
+        return Ok(bytes);
+    }
+    Err(CliError::Custom(format!(
+        "No Anki collection found in {}",
+        path.display()
+    )))
+}
+
+fn staging_path() -> PathBuf {
+    std::env::temp_dir().join(format!("immerse-{}.anki2", std::process::id()))
+}
+
+fn has_table(connection: &Connection, table: &str) -> Result<bool, CliError> {
+    let count: usize = connection.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?",
+        [table],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+type Decks = HashMap<i64, String>;
+type Models = HashMap<i64, Model>;
+
+/// Schema 11 collections keep decks and note types as JSON in the col table
+fn read_legacy_col(connection: &Connection) -> Result<(Decks, Models), CliError> {
+    let (decks, models): (String, String) =
+        connection.query_row("SELECT decks, models FROM col", [], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+    let decks: HashMap<String, LegacyDeck> = serde_json::from_str(&decks)?;
+    let models: HashMap<String, LegacyModel> = serde_json::from_str(&models)?;
+    Ok((
+        decks
+            .into_iter()
+            .filter_map(|(id, deck)| Some((id.parse().ok()?, deck.name)))
+            .collect(),
+        models
+            .into_iter()
+            .filter_map(|(id, model)| {
+                let fields = model.flds.into_iter().map(|f| (f.name, f.ord)).collect();
+                Some((
+                    id.parse().ok()?,
+                    Model {
+                        name: model.name,
+                        fields,
+                    },
+                ))
+            })
+            .collect(),
+    ))
+}
+
+/// Newer collections have deck and field tables, with deck levels separated by 0x1f
+fn read_decks(connection: &Connection) -> Result<Decks, CliError> {
+    let mut stmt = connection.prepare("SELECT id, name FROM decks")?;
+    let decks = stmt
+        .query_map([], |row| {
+            let name: String = row.get(1)?;
+            Ok((row.get(0)?, name.replace(FIELD_SEPARATOR, "::")))
+        })?
+        .collect::<Result<_, _>>()?;
+    Ok(decks)
+}
+
+fn read_reviews(connection: &Connection) -> Result<HashMap<u64, Vec<AnkiReview>>, CliError> {
+    let mut stmt = connection.prepare("SELECT cid, id, ease FROM revlog ORDER BY id")?;
+    let mut rows = stmt.query([])?;
+    let mut reviews: HashMap<u64, Vec<AnkiReview>> = HashMap::new();
+    while let Some(row) = rows.next()? {
+        reviews.entry(row.get(0)?).or_default().push(AnkiReview {
+            id: row.get(1)?,
+            ease: row.get(2)?,
+        });
+    }
+    Ok(reviews)
+}
+
+fn read_fields(connection: &Connection) -> Result<Models, CliError> {
+    let mut stmt = connection.prepare(
+        "SELECT fields.ntid, notetypes.name, fields.name, fields.ord
+        FROM fields JOIN notetypes ON notetypes.id = fields.ntid
+        ORDER BY fields.ntid, fields.ord",
+    )?;
+    let mut rows = stmt.query([])?;
+    let mut models: Models = HashMap::new();
+    while let Some(row) = rows.next()? {
+        let model = models.entry(row.get(0)?).or_default();
+        model.name = row.get(1)?;
+        model.fields.push((row.get(2)?, row.get(3)?));
+    }
+    Ok(models)
+}
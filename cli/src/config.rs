@@ -1,4 +1,5 @@
 use std::{
+    collections::BTreeMap,
     fs,
     io::Write,
 };
@@ -8,15 +9,84 @@ use serde::{Deserialize, Serialize};
 
 use crate::CliError;
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Debug, Default)]
 pub struct Config {
     pub decks: Vec<DeckConfig>,
+    /// Personalised FSRS weights produced by OptimizeParams
+    #[serde(default)]
+    pub fsrs_params: Option<Vec<f64>>,
+    #[serde(default)]
+    pub ankiconnect: AnkiConnectConfig,
+    #[serde(default)]
+    pub exposure: ExposureConfig,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct AnkiConnectConfig {
+    pub url: String,
+    /// Must match the apiKey setting of AnkiConnect, when one is set
+    pub api_key: Option<String>,
+    pub timeout_secs: u64,
+    /// How many times to retry a request that could not reach Anki
+    pub retries: u32,
+    pub retry_delay_ms: u64,
+}
+
+impl Default for AnkiConnectConfig {
+    fn default() -> Self {
+        AnkiConnectConfig {
+            url: "http://localhost:8765".to_string(),
+            api_key: None,
+            timeout_secs: 30,
+            retries: 2,
+            retry_delay_ms: 500,
+        }
+    }
+}
+
+/// Kanji exposure recorded from text sent to /ws/hook
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct ExposureConfig {
+    /// Occurrences after which a kanji not in any Anki deck is promoted
+    /// to Seen, or None to never promote
+    pub promote_after: Option<u32>,
+}
+
+impl Default for ExposureConfig {
+    fn default() -> Self {
+        ExposureConfig {
+            promote_after: Some(10),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct DeckConfig {
     pub name: String,
+    /// Field to parse words from for note types without their own mapping
     pub word_field: String,
+    /// Word field for each note type (`modelName`) that stores it elsewhere
+    #[serde(default)]
+    pub note_fields: BTreeMap<String, String>,
+}
+
+impl DeckConfig {
+    pub fn new(name: String, word_field: String) -> DeckConfig {
+        DeckConfig {
+            name,
+            word_field,
+            note_fields: BTreeMap::new(),
+        }
+    }
+
+    /// The field holding the word on notes of the given type
+    pub fn field_for(&self, model_name: &str) -> &str {
+        self.note_fields
+            .get(model_name)
+            .unwrap_or(&self.word_field)
+    }
 }
 
 pub fn read_config() -> Result<Config, CliError> {
@@ -27,7 +97,7 @@ pub fn read_config() -> Result<Config, CliError> {
         if !proj_dirs.data_dir().join("config.json").exists() {
             let mut config_file = fs::File::create(proj_dirs.data_dir().join("config.json"))?;
             println!("0");
-            let config = Config { decks: vec![] };
+            let config = Config::default();
             config_file.write_all(serde_json::to_string(&config)?.as_bytes())?;
             println!("1");
             return Ok(config);
@@ -40,24 +110,72 @@ pub fn read_config() -> Result<Config, CliError> {
     }
 }
 
-pub fn add_deck(deck_name: String, deck_field: String) -> Result<(), CliError> {
+pub fn add_deck(deck_config: DeckConfig) -> Result<(), CliError> {
     let mut config = read_config()?;
-    if config.decks.iter().any(|deck| deck.name == deck_name) {
+    if config.decks.iter().any(|deck| deck.name == deck_config.name) {
         return Ok(());
     } else {
-        config.decks.push(DeckConfig {
-            name: deck_name,
-            word_field: deck_field,
-        });
-        let data_dir = ProjectDirs::from("com", "Immerse", "Immerse");
-        match data_dir {
-            Some(dir) => {
-                let mut config_file = fs::File::create(dir.data_dir().join("config.json"))?;
-                config_file.write_all(serde_json::to_string(&config)?.as_bytes())?;
-                println!("3");
-            }
-            None => return Err(CliError::Custom("Error Writing to Data Dir".into())),
+        config.decks.push(deck_config);
+        write_config(&config)?;
+    }
+    Ok(())
+}
+
+/// Remove a deck from the config, returning whether it was present
+pub fn remove_deck(deck_name: &str) -> Result<bool, CliError> {
+    let mut config = read_config()?;
+    let len = config.decks.len();
+    config.decks.retain(|deck| deck.name != deck_name);
+    if config.decks.len() == len {
+        return Ok(false);
+    }
+    write_config(&config)?;
+    Ok(true)
+}
+
+/// Modify a configured deck, returning the updated deck if it exists
+pub fn update_deck(
+    deck_name: &str,
+    update: impl FnOnce(&mut DeckConfig),
+) -> Result<Option<DeckConfig>, CliError> {
+    let mut config = read_config()?;
+    let deck = match config.decks.iter_mut().find(|deck| deck.name == deck_name) {
+        Some(deck) => {
+            update(deck);
+            deck.clone()
+        }
+        None => return Ok(None),
+    };
+    write_config(&config)?;
+    Ok(Some(deck))
+}
+
+/// Map a note type in a configured deck to its word field, returning whether the deck exists
+pub fn set_note_field(
+    deck_name: &str,
+    model_name: String,
+    field: String,
+) -> Result<bool, CliError> {
+    Ok(update_deck(deck_name, |deck| {
+        deck.note_fields.insert(model_name, field);
+    })?
+    .is_some())
+}
+
+pub fn set_fsrs_params(weights: Vec<f64>) -> Result<(), CliError> {
+    let mut config = read_config()?;
+    config.fsrs_params = Some(weights);
+    write_config(&config)
+}
+
+pub fn write_config(config: &Config) -> Result<(), CliError> {
+    let data_dir = ProjectDirs::from("com", "Immerse", "Immerse");
+    match data_dir {
+        Some(dir) => {
+            let mut config_file = fs::File::create(dir.data_dir().join("config.json"))?;
+            config_file.write_all(serde_json::to_string(config)?.as_bytes())?;
         }
+        None => return Err(CliError::Custom("Error Writing to Data Dir".into())),
     }
     Ok(())
 }
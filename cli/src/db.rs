@@ -1,57 +1,644 @@
+use std::collections::{HashMap, HashSet};
 use std::fs;
 
-use crate::kanji::{is_kanji, KanjiEntry};
+use crate::kanji::{is_kanji, KanjiEntry, KanjiInfo, Knowledge};
+use crate::migrate::migrate;
 use crate::CliError;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use directories::ProjectDirs;
-use fsrs::Card;
-use rusqlite::{params, Connection};
+use fsrs::{Card, Rating, ReviewLog, State};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Transaction};
+use serde::Serialize;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct KanjiSrs {
     pub kanji: char,
     pub card: Card,
 }
 
-pub fn add_kanji(kanji: char, level: u8) -> Result<(), CliError> {
-    let connection = connect()?;
-    connection.execute(
+/// A single answered review, as recorded in the review_log table
+#[derive(Debug, Clone, Serialize)]
+pub struct KanjiReviewLog {
+    pub kanji: char,
+    pub log: ReviewLog,
+    pub state_after: State,
+    /// Time spent answering, in milliseconds
+    pub duration: u64,
+}
+
+/// A vocabulary word parsed from an Anki card's word field. The card is
+/// the source backing each kanji in the word.
+#[derive(Debug, Clone, Serialize)]
+pub struct Word {
+    pub card_id: u64,
+    pub note_id: u64,
+    pub deck: String,
+    /// Field text without markup or furigana
+    pub word: String,
+    /// Reading of the whole word, when the field had furigana
+    pub reading: Option<String>,
+    pub interval: u16,
+    /// Anki modification time, in seconds
+    pub modified: i64,
+}
+
+impl Word {
+    pub fn kanji(&self) -> impl Iterator<Item = char> + '_ {
+        self.word.chars().filter(|c| is_kanji(*c))
+    }
+}
+
+/// Where a deck's last sync got to
+pub struct SyncCheckpoint {
+    pub field: String,
+    pub synced_at: i64,
+}
+
+/// A card whose note type has no word field mapped. It is remembered with
+/// its modification time so incremental syncs only fetch it again once it
+/// changes, and its note type is still reported as unmapped.
+#[derive(Debug, Clone)]
+pub struct SkippedCard {
+    pub card_id: u64,
+    pub deck: String,
+    pub model: String,
+    /// Field names of the note type, in order
+    pub fields: Vec<String>,
+    pub modified: i64,
+}
+
+/// Everything an Anki sync writes
+#[derive(Default)]
+pub struct SyncChanges {
+    pub deck: String,
+    pub field: String,
+    pub synced_at: i64,
+    /// Words from new or changed source cards
+    pub cards: Vec<Word>,
+    /// Ids of source cards that were deleted or no longer have the word field
+    pub removed: Vec<u64>,
+    /// Recomputed levels of every kanji whose source cards changed
+    pub levels: HashMap<char, Knowledge>,
+    /// Most stable state seeded from the fetched cards' review history
+    pub seeds: HashMap<char, Card>,
+    /// Kanji left without any source card
+    pub orphaned: Vec<char>,
+    /// (card id, kanji, reading) for each kanji reading given by a changed card
+    pub readings: Vec<(u64, char, String)>,
+    /// Fetched cards without a word field
+    pub skipped: Vec<SkippedCard>,
+}
+
+/// Write the result of an Anki sync: update the stored source cards, replace
+/// the recomputed kanji levels, give each kanji a srs card unless it already
+/// has one and move the deck's checkpoint. Levels set by hand are only ever
+/// raised by a sync, never lowered. The caller runs this in the transaction
+/// the sync was planned in, so if any write fails nothing is stored.
+pub fn write_sync(tx: &Connection, changes: &SyncChanges) -> Result<(), CliError> {
+    let mut remove = tx.prepare_cached("DELETE FROM words WHERE card_id = ?")?;
+    let mut remove_readings = tx.prepare_cached("DELETE FROM kanji_reading WHERE card_id = ?")?;
+    let mut remove_kanji = tx.prepare_cached("DELETE FROM word_kanji WHERE card_id = ?")?;
+    for id in &changes.removed {
+        remove.execute([id])?;
+    }
+    let changed = changes.cards.iter().map(|card| &card.card_id);
+    for id in changes.removed.iter().chain(changed) {
+        remove_readings.execute([id])?;
+        remove_kanji.execute([id])?;
+    }
+    let mut reading = tx.prepare_cached(
+        "INSERT OR IGNORE INTO kanji_reading (card_id, kanji, reading) VALUES (?, ?, ?)",
+    )?;
+    for (id, kanji, kana) in &changes.readings {
+        reading.execute((id, kanji.to_string(), kana))?;
+    }
+    let mut word = tx.prepare_cached(
         "
-        CREATE TABLE IF NOT EXISTS kanji (
-            kanji TEXT NOT NULL PRIMARY KEY,
-            level INTEGER
-        )",
-        [],
+        INSERT INTO words (card_id, note_id, deck, word, reading, interval, modified)
+        VALUES (?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT(card_id) DO UPDATE SET
+            note_id = excluded.note_id,
+            deck = excluded.deck,
+            word = excluded.word,
+            reading = excluded.reading,
+            interval = excluded.interval,
+            modified = excluded.modified
+    ",
     )?;
-    if !is_kanji(kanji) {
-        return Ok(());
+    let mut word_kanji =
+        tx.prepare_cached("INSERT OR IGNORE INTO word_kanji (card_id, kanji) VALUES (?, ?)")?;
+    for card in &changes.cards {
+        word.execute((
+            card.card_id,
+            card.note_id,
+            &card.deck,
+            &card.word,
+            &card.reading,
+            card.interval,
+            card.modified,
+        ))?;
+        for kanji in card.kanji() {
+            word_kanji.execute((card.card_id, kanji.to_string()))?;
+        }
+    }
+
+    let mut level = tx.prepare_cached(
+        "
+        INSERT INTO kanji (kanji, level) VALUES (?, ?)
+        ON CONFLICT(kanji) DO UPDATE SET
+            level = CASE WHEN kanji.manual = 1
+                THEN MAX(kanji.level, excluded.level)
+                ELSE excluded.level END,
+            orphaned = 0
+    ",
+    )?;
+    let new_card = Card::new();
+    let mut card = tx.prepare_cached(
+        "
+        INSERT INTO srs (kanji, card, due, status) VALUES (?, ?, ?, ?)
+        ON CONFLICT(kanji) DO NOTHING
+    ",
+    )?;
+    // Only the fetched cards are seeded, so a stored seed from a word that
+    // wasn't fetched this time is kept when it is more stable
+    let mut stored_card = tx.prepare_cached("SELECT card FROM srs WHERE kanji = ?")?;
+    // Seeded state replaces the stored card until it is first reviewed here
+    let mut seeded = tx.prepare_cached(
+        "
+        INSERT INTO srs (kanji, card, due, status) VALUES (?, ?, ?, ?)
+        ON CONFLICT(kanji) DO UPDATE SET
+            card = excluded.card,
+            due = excluded.due,
+            status = excluded.status
+        WHERE NOT EXISTS (SELECT 1 FROM review_log WHERE review_log.kanji = srs.kanji)
+    ",
+    )?;
+    for (kanji, knowledge) in &changes.levels {
+        if !is_kanji(*kanji) {
+            continue;
+        }
+        level.execute((kanji.to_string(), knowledge))?;
+        let seed = match changes.seeds.get(kanji) {
+            Some(seed) => {
+                let stored: Option<Vec<u8>> = stored_card
+                    .query_row([kanji.to_string()], |row| row.get(0))
+                    .optional()?;
+                let stronger = match stored {
+                    Some(blob) => bson::from_slice::<Card>(&blob)?.stability >= seed.stability,
+                    None => false,
+                };
+                (!stronger).then_some(seed)
+            }
+            None => None,
+        };
+        let (stmt, initial) = match seed {
+            Some(seed) => (&mut seeded, seed),
+            None => (&mut card, &new_card),
+        };
+        let bson_data = bson::to_vec(initial).map_err(|err| CliError::Custom(err.to_string()))?;
+        stmt.execute((
+            kanji.to_string(),
+            bson_data,
+            initial.due.timestamp(),
+            initial.state as u8,
+        ))?;
+    }
+
+    let mut unskip = tx.prepare_cached("DELETE FROM skipped_card WHERE card_id = ?")?;
+    let changed = changes.cards.iter().map(|card| &card.card_id);
+    for id in changes.removed.iter().chain(changed) {
+        unskip.execute([id])?;
+    }
+    let mut skip = tx.prepare_cached(
+        "
+        INSERT OR REPLACE INTO skipped_card (card_id, deck, model, fields, modified)
+        VALUES (?, ?, ?, ?, ?)
+    ",
+    )?;
+    for card in &changes.skipped {
+        skip.execute((
+            card.card_id,
+            &card.deck,
+            &card.model,
+            serde_json::to_string(&card.fields)?,
+            card.modified,
+        ))?;
+    }
+
+    let mut orphan = tx.prepare_cached("UPDATE kanji SET orphaned = 1 WHERE kanji = ?")?;
+    for kanji in &changes.orphaned {
+        orphan.execute([kanji.to_string()])?;
+    }
+
+    tx.execute(
+        "
+        INSERT INTO sync_checkpoint (deck, field, synced_at) VALUES (?, ?, ?)
+        ON CONFLICT(deck) DO UPDATE SET
+            field = excluded.field,
+            synced_at = excluded.synced_at
+    ",
+        (&changes.deck, &changes.field, changes.synced_at),
+    )?;
+    Ok(())
+}
+
+const WORD_COLUMNS: &str = "words.card_id, words.note_id, words.deck, words.word, \
+    words.reading, words.interval, words.modified";
+
+fn word_from_row(row: &rusqlite::Row) -> rusqlite::Result<Word> {
+    Ok(Word {
+        card_id: row.get(0)?,
+        note_id: row.get(1)?,
+        deck: row.get(2)?,
+        word: row.get(3)?,
+        reading: row.get(4)?,
+        interval: row.get(5)?,
+        modified: row.get(6)?,
+    })
+}
+
+/// Stored words of every deck, by source card id
+pub fn words_by_card(connection: &Connection) -> Result<HashMap<u64, Word>, CliError> {
+    let mut stmt = connection.prepare(&format!("SELECT {} FROM words", WORD_COLUMNS))?;
+    let rows = stmt.query_map([], word_from_row)?;
+    let mut cards = HashMap::new();
+    for card in rows {
+        let card = card?;
+        cards.insert(card.card_id, card);
     }
+    Ok(cards)
+}
+
+/// Words with an Anki interval of at least a day, longest interval first.
+/// A note with several cards is listed once, by its best card.
+pub fn known_words() -> Result<Vec<Word>, CliError> {
+    let connection = connect()?;
+    let mut stmt = connection.prepare(&format!(
+        "SELECT {}, MAX(words.interval) FROM words
+        WHERE words.interval > 0
+        GROUP BY words.word, words.note_id
+        ORDER BY words.interval DESC, words.word",
+        WORD_COLUMNS
+    ))?;
+    let rows = stmt.query_map([], word_from_row)?;
+    let mut words = vec![];
+    for word in rows {
+        words.push(word?);
+    }
+    Ok(words)
+}
+
+/// Known words (interval > 0) containing a kanji, longest interval first
+pub fn words_with_kanji(kanji: char) -> Result<Vec<Word>, CliError> {
+    let connection = connect()?;
+    let mut stmt = connection.prepare(&format!(
+        "SELECT {}, MAX(words.interval) FROM words
+        JOIN word_kanji ON word_kanji.card_id = words.card_id
+        WHERE word_kanji.kanji = ? AND words.interval > 0
+        GROUP BY words.word, words.note_id
+        ORDER BY words.interval DESC, words.word",
+        WORD_COLUMNS
+    ))?;
+    let rows = stmt.query_map([kanji.to_string()], word_from_row)?;
+    let mut words = vec![];
+    for word in rows {
+        words.push(word?);
+    }
+    Ok(words)
+}
+
+pub fn sync_checkpoint(
+    connection: &Connection,
+    deck: &str,
+) -> Result<Option<SyncCheckpoint>, CliError> {
+    let mut stmt =
+        connection.prepare("SELECT field, synced_at FROM sync_checkpoint WHERE deck = ?")?;
+    let checkpoint = stmt
+        .query_row([deck], |row| {
+            Ok(SyncCheckpoint {
+                field: row.get(0)?,
+                synced_at: row.get(1)?,
+            })
+        })
+        .optional()?;
+    Ok(checkpoint)
+}
+
+/// Cards of a deck skipped for lacking a word field
+pub fn skipped_cards(connection: &Connection, deck: &str) -> Result<Vec<SkippedCard>, CliError> {
+    let mut stmt = connection.prepare(
+        "SELECT card_id, deck, model, fields, modified FROM skipped_card WHERE deck = ?",
+    )?;
+    let mut rows = stmt.query([deck])?;
+    let mut cards = vec![];
+    while let Some(row) = rows.next()? {
+        let fields: String = row.get(3)?;
+        cards.push(SkippedCard {
+            card_id: row.get(0)?,
+            deck: row.get(1)?,
+            model: row.get(2)?,
+            fields: serde_json::from_str(&fields)?,
+            modified: row.get(4)?,
+        });
+    }
+    Ok(cards)
+}
 
-    let mut q = connection.prepare("SELECT kanji, level FROM kanji WHERE kanji = ?")?;
-    let mut result = q.query([kanji.to_string()])?;
-    match result.next() {
-        Ok(r) => {
-            if r.is_some() {
-                let mut stmt =
-                    connection.prepare("UPDATE kanji SET level = MAX(level, ?) WHERE kanji = ?")?;
-                stmt.execute((level, kanji.to_string()))?;
-            } else {
-                let mut stmt =
-                    connection.prepare("INSERT INTO kanji (kanji, level) VALUES (?,?)")?;
-                stmt.execute((kanji.to_string(), level))?;
+/// KANJIDIC2 on and kun readings of every imported kanji
+pub fn dictionary_readings(
+    connection: &Connection,
+) -> Result<HashMap<char, Vec<String>>, CliError> {
+    let mut stmt = connection.prepare("SELECT kanji, reading FROM kanji_dict_reading")?;
+    let mut rows = stmt.query([])?;
+    let mut readings: HashMap<char, Vec<String>> = HashMap::new();
+    while let Some(row) = rows.next()? {
+        let kanji: String = row.get(0)?;
+        if let Some(kanji) = kanji.chars().next() {
+            readings.entry(kanji).or_default().push(row.get(1)?);
+        }
+    }
+    Ok(readings)
+}
+
+/// Distinct readings of a kanji met in synced vocabulary
+pub fn met_readings(kanji: char) -> Result<Vec<String>, CliError> {
+    let connection = connect()?;
+    let mut stmt = connection.prepare(
+        "SELECT reading FROM kanji_reading WHERE kanji = ? GROUP BY reading ORDER BY COUNT(*) DESC",
+    )?;
+    let rows = stmt.query_map([kanji.to_string()], |row| row.get(0))?;
+    let mut readings = vec![];
+    for reading in rows {
+        readings.push(reading?);
+    }
+    Ok(readings)
+}
+
+/// Kanji whose Anki cards have all been deleted
+pub fn orphaned_kanji() -> Result<Vec<KanjiEntry>, CliError> {
+    let connection = connect()?;
+    let mut stmt = connection.prepare("SELECT kanji, level FROM kanji WHERE orphaned = 1")?;
+    let rows = stmt.query_map([], |row| {
+        Ok(KanjiEntry {
+            kanji: row.get(0)?,
+            level: row.get(1)?,
+        })
+    })?;
+    let mut kanji = vec![];
+    for entry in rows {
+        kanji.push(entry?);
+    }
+    Ok(kanji)
+}
+
+/// Log lines of immersion text and count the kanji in them, returning the
+/// kanji promoted to Seen
+pub fn record_exposure(
+    lines: &[String],
+    seen_at: i64,
+    promote_after: Option<u32>,
+) -> Result<Vec<char>, CliError> {
+    let mut connection = connect()?;
+    let mut promoted = vec![];
+    for line in lines {
+        promoted.extend(log_exposure(&mut connection, line, seen_at, promote_after)?);
+    }
+    Ok(promoted)
+}
+
+/// Kanji met `promote_after` times that no synced word contains and that are
+/// still unknown become Seen. Levels set by sync or by hand are left alone.
+pub fn log_exposure(
+    connection: &mut Connection,
+    line: &str,
+    seen_at: i64,
+    promote_after: Option<u32>,
+) -> Result<Vec<char>, CliError> {
+    let tx = connection.transaction()?;
+    tx.execute(
+        "INSERT INTO hook_line (line, received_at) VALUES (?, ?)",
+        (line, seen_at),
+    )?;
+    let promoted = count_exposure(&tx, line, seen_at, promote_after)?;
+    tx.commit()?;
+    Ok(promoted)
+}
+
+/// Record chapters of a book as read: count their kanji as exposure and move
+/// the book's progress to `chapter`. Returns the kanji promoted to Seen.
+pub fn record_book_reading(
+    book: &str,
+    chapter: usize,
+    text: &str,
+    seen_at: i64,
+    promote_after: Option<u32>,
+) -> Result<Vec<char>, CliError> {
+    let mut connection = connect()?;
+    let tx = connection.transaction()?;
+    let promoted = count_exposure(&tx, text, seen_at, promote_after)?;
+    tx.execute(
+        "
+        INSERT INTO book_progress (book, chapter, updated_at) VALUES (?, ?, ?)
+        ON CONFLICT(book) DO UPDATE SET
+            chapter = excluded.chapter,
+            updated_at = excluded.updated_at
+    ",
+        (book, chapter, seen_at),
+    )?;
+    tx.commit()?;
+    Ok(promoted)
+}
+
+/// The last chapter of a book recorded as read
+pub fn book_progress(book: &str) -> Result<Option<usize>, CliError> {
+    let connection = connect()?;
+    let chapter = connection
+        .query_row(
+            "SELECT chapter FROM book_progress WHERE book = ?",
+            [book],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(chapter)
+}
+
+fn count_exposure(
+    tx: &Transaction,
+    text: &str,
+    seen_at: i64,
+    promote_after: Option<u32>,
+) -> Result<Vec<char>, CliError> {
+    let mut promoted = vec![];
+    {
+        let mut counts: HashMap<char, u32> = HashMap::new();
+        for kanji in text.chars().filter(|c| is_kanji(*c)) {
+            *counts.entry(kanji).or_default() += 1;
+        }
+        let mut exposure = tx.prepare_cached(
+            "
+            INSERT INTO kanji_exposure (kanji, count, last_seen) VALUES (?, ?, ?)
+            ON CONFLICT(kanji) DO UPDATE SET
+                count = count + excluded.count,
+                last_seen = excluded.last_seen
+            RETURNING count
+        ",
+        )?;
+        let mut promote = tx.prepare_cached(
+            "
+            INSERT INTO kanji (kanji, level) SELECT ?1, ?2
+            WHERE NOT EXISTS (SELECT 1 FROM word_kanji WHERE word_kanji.kanji = ?1)
+            ON CONFLICT(kanji) DO UPDATE SET level = excluded.level WHERE kanji.level = 0
+        ",
+        )?;
+        for (kanji, count) in counts {
+            let total: u32 =
+                exposure.query_row((kanji.to_string(), count, seen_at), |row| row.get(0))?;
+            if promote_after.is_some_and(|threshold| total >= threshold)
+                && promote.execute((kanji.to_string(), Knowledge::Seen))? > 0
+            {
+                promoted.push(kanji);
             }
         }
-        Err(_) => {
-            let mut stmt = connection.prepare("INSERT INTO kanji (kanji, level) VALUES (?,?)")?;
-            stmt.execute((kanji.to_string(), level))?;
+    }
+    promoted.sort();
+    Ok(promoted)
+}
+
+/// How often each kanji has been met in text sent to /ws/hook or in books read
+pub fn exposure_counts() -> Result<Vec<(char, u32)>, CliError> {
+    let connection = connect()?;
+    let mut stmt = connection.prepare("SELECT kanji, count FROM kanji_exposure")?;
+    let mut rows = stmt.query([])?;
+    let mut counts = vec![];
+    while let Some(row) = rows.next()? {
+        let kanji: String = row.get(0)?;
+        if let Some(kanji) = kanji.chars().next() {
+            counts.push((kanji, row.get(1)?));
+        }
+    }
+    Ok(counts)
+}
+
+/// Kanji that have been learned, as opposed to only met. Seen only counts
+/// when it comes from Anki or was set by hand, not from exposure alone.
+pub fn learned_kanji() -> Result<HashSet<char>, CliError> {
+    let connection = connect()?;
+    learned_kanji_from(&connection)
+}
+
+pub fn learned_kanji_from(connection: &Connection) -> Result<HashSet<char>, CliError> {
+    let mut stmt = connection.prepare(
+        "
+        SELECT kanji FROM kanji
+        WHERE level > ?1 OR (level = ?1 AND (
+            manual = 1 OR EXISTS (SELECT 1 FROM word_kanji WHERE word_kanji.kanji = kanji.kanji)
+        ))
+    ",
+    )?;
+    let rows = stmt.query_map([Knowledge::Seen], |row| row.get::<_, String>(0))?;
+    let mut kanji = HashSet::new();
+    for entry in rows {
+        kanji.extend(entry?.chars().next());
+    }
+    Ok(kanji)
+}
+
+/// Components of every kanji with component data
+pub fn all_components() -> Result<HashMap<char, Vec<char>>, CliError> {
+    let connection = connect()?;
+    let mut stmt = connection.prepare("SELECT kanji, component FROM kanji_component")?;
+    let mut rows = stmt.query([])?;
+    let mut components: HashMap<char, Vec<char>> = HashMap::new();
+    while let Some(row) = rows.next()? {
+        let kanji: String = row.get(0)?;
+        let component: String = row.get(1)?;
+        if let (Some(kanji), Some(component)) = (kanji.chars().next(), component.chars().next()) {
+            components.entry(kanji).or_default().push(component);
+        }
+    }
+    Ok(components)
+}
+
+/// Kanji that already have a srs card
+pub fn srs_kanji() -> Result<Vec<char>, CliError> {
+    let connection = connect()?;
+    let mut stmt = connection.prepare("SELECT kanji FROM srs")?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    let mut kanji = vec![];
+    for entry in rows {
+        kanji.extend(entry?.chars().next());
+    }
+    Ok(kanji)
+}
+
+/// Give each kanji a New srs card unless it already has one, returning how
+/// many were added
+pub fn add_new_cards(kanji: &[char]) -> Result<usize, CliError> {
+    let mut connection = connect()?;
+    let tx = connection.transaction()?;
+    let mut added = 0;
+    {
+        let card = Card::new();
+        let bson_data = bson::to_vec(&card).map_err(|err| CliError::Custom(err.to_string()))?;
+        let mut insert = tx.prepare_cached(
+            "
+            INSERT INTO srs (kanji, card, due, status) VALUES (?, ?, ?, ?)
+            ON CONFLICT(kanji) DO NOTHING
+        ",
+        )?;
+        for k in kanji.iter().filter(|k| is_kanji(**k)) {
+            added += insert.execute((
+                k.to_string(),
+                &bson_data,
+                card.due.timestamp(),
+                card.state as u8,
+            ))?;
         }
     }
+    tx.commit()?;
+    Ok(added)
+}
+
+/// Set a kanji's level, replacing whatever level it had before
+pub fn set_kanji_level(kanji: char, level: Knowledge) -> Result<(), CliError> {
+    let connection = connect()?;
+    store_kanji_level(&connection, kanji, level)
+}
+
+/// Levels stored here are marked as set by hand, so syncs don't lower them
+pub fn store_kanji_level(
+    connection: &Connection,
+    kanji: char,
+    level: Knowledge,
+) -> Result<(), CliError> {
+    if !is_kanji(kanji) {
+        return Err(CliError::Custom(format!("{} is not a kanji", kanji)));
+    }
+    connection.execute(
+        "
+        INSERT INTO kanji (kanji, level, manual) VALUES (?, ?, 1)
+        ON CONFLICT(kanji) DO UPDATE SET level = excluded.level, manual = 1
+    ",
+        (kanji.to_string(), level),
+    )?;
     Ok(())
 }
 
+pub fn get_kanji(kanji: char) -> Result<KanjiEntry, CliError> {
+    let connection = connect()?;
+    let mut stmt = connection.prepare("SELECT kanji, level FROM kanji WHERE kanji = ?")?;
+    let entry = stmt.query_row(params![kanji.to_string()], |row| {
+        Ok(KanjiEntry {
+            kanji: row.get(0)?,
+            level: row.get(1)?,
+        })
+    })?;
+    Ok(entry)
+}
+
 pub fn wipe_srs_db() -> Result<(), CliError> {
     let connection = connect()?;
-    connection.execute("DROP TABLE srs", [])?;
+    connection.execute_batch("DELETE FROM srs; DELETE FROM review_log;")?;
     Ok(())
 }
 
@@ -118,20 +705,306 @@ pub fn cards_with_status(status: fsrs::State) -> Result<Vec<KanjiSrs>, CliError>
     Ok(due)
 }
 
-pub fn ensure_card_db() -> Result<(), CliError> {
+pub fn log_review(entry: &KanjiReviewLog) -> Result<(), CliError> {
     let connection = connect()?;
-    connection.execute(
-        "CREATE TABLE IF NOT EXISTS srs (
-            kanji TEXT NOT NULL PRIMARY KEY,
-            card BLOB,
-            due INTEGER,
-            status INTEGER
-        )",
-        [],
+    let mut q = connection.prepare(
+        "
+        INSERT INTO review_log
+            (kanji, rating, reviewed, elapsed_days, scheduled_days, state_before, state_after, duration)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+    ",
+    )?;
+    q.execute((
+        entry.kanji.to_string(),
+        entry.log.rating as u8,
+        entry.log.reviewed_date.timestamp(),
+        entry.log.elapsed_days,
+        entry.log.scheduled_days,
+        entry.log.state as u8,
+        entry.state_after as u8,
+        entry.duration,
+    ))?;
+    Ok(())
+}
+
+/// Every stored review, oldest first
+pub fn review_logs() -> Result<Vec<KanjiReviewLog>, CliError> {
+    query_review_logs("", ())
+}
+
+/// Stored reviews of a single kanji, oldest first
+pub fn review_logs_for(kanji: char) -> Result<Vec<KanjiReviewLog>, CliError> {
+    query_review_logs("WHERE kanji = ?", params![kanji.to_string()])
+}
+
+fn query_review_logs(
+    filter: &str,
+    params: impl rusqlite::Params,
+) -> Result<Vec<KanjiReviewLog>, CliError> {
+    let conn = connect()?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT kanji, rating, reviewed, elapsed_days, scheduled_days, state_before, state_after, duration
+        FROM review_log {} ORDER BY reviewed, id",
+        filter
+    ))?;
+    let mut res = stmt.query(params)?;
+    let mut logs: Vec<KanjiReviewLog> = vec![];
+    while let Some(row) = res.next()? {
+        let str: String = row.get(0)?;
+        let kanji = str.chars().next().ok_or_else(|| CliError::Custom("DB KANJI ERROR".to_string()))?;
+        let reviewed: i64 = row.get(2)?;
+        logs.push(KanjiReviewLog {
+            kanji,
+            log: ReviewLog {
+                rating: rating_from_db(row.get(1)?)?,
+                elapsed_days: row.get(3)?,
+                scheduled_days: row.get(4)?,
+                state: state_from_db(row.get(5)?)?,
+                reviewed_date: DateTime::from_timestamp(reviewed, 0)
+                    .ok_or_else(|| CliError::Custom("DB TIMESTAMP ERROR".to_string()))?,
+            },
+            state_after: state_from_db(row.get(6)?)?,
+            duration: row.get(7)?,
+        })
+    }
+    Ok(logs)
+}
+
+pub fn rating_from_db(rating: u8) -> Result<Rating, CliError> {
+    match rating {
+        1 => Ok(Rating::Again),
+        2 => Ok(Rating::Hard),
+        3 => Ok(Rating::Good),
+        4 => Ok(Rating::Easy),
+        _ => Err(CliError::Custom(format!("Invalid rating {} in review log", rating))),
+    }
+}
+
+fn state_from_db(state: u8) -> Result<State, CliError> {
+    match state {
+        0 => Ok(State::New),
+        1 => Ok(State::Learning),
+        2 => Ok(State::Review),
+        3 => Ok(State::Relearning),
+        _ => Err(CliError::Custom(format!("Invalid card state {} in review log", state))),
+    }
+}
+
+/// Replace the dictionary tables with freshly imported entries
+pub fn import_kanji_info(entries: &[KanjiInfo]) -> Result<(), CliError> {
+    let mut connection = connect()?;
+    let tx = connection.transaction()?;
+    tx.execute_batch(
+        "
+        DELETE FROM kanji_info;
+        DELETE FROM kanji_dict_reading;
+        DELETE FROM kanji_meaning;
+        ",
     )?;
+    {
+        let mut info = tx.prepare(
+            "INSERT INTO kanji_info (kanji, stroke_count, grade, jlpt, frequency) VALUES (?, ?, ?, ?, ?)",
+        )?;
+        let mut reading = tx.prepare(
+            "INSERT INTO kanji_dict_reading (kanji, reading, type, position) VALUES (?, ?, ?, ?)",
+        )?;
+        let mut meaning =
+            tx.prepare("INSERT INTO kanji_meaning (kanji, meaning, position) VALUES (?, ?, ?)")?;
+        for entry in entries {
+            let kanji = entry.kanji.to_string();
+            info.execute((
+                &kanji,
+                entry.stroke_count,
+                entry.grade,
+                entry.jlpt,
+                entry.frequency,
+            ))?;
+            for (i, on) in entry.on_readings.iter().enumerate() {
+                reading.execute((&kanji, on, "on", i))?;
+            }
+            for (i, kun) in entry.kun_readings.iter().enumerate() {
+                reading.execute((&kanji, kun, "kun", i))?;
+            }
+            for (i, m) in entry.meanings.iter().enumerate() {
+                meaning.execute((&kanji, m, i))?;
+            }
+        }
+    }
+    tx.commit()?;
     Ok(())
 }
 
+pub fn get_kanji_info(kanji: char) -> Result<Option<KanjiInfo>, CliError> {
+    let connection = connect()?;
+    let mut stmt = connection
+        .prepare("SELECT stroke_count, grade, jlpt, frequency FROM kanji_info WHERE kanji = ?")?;
+    let mut rows = stmt.query(params![kanji.to_string()])?;
+    let mut info = match rows.next()? {
+        Some(row) => KanjiInfo {
+            kanji,
+            stroke_count: row.get(0)?,
+            grade: row.get(1)?,
+            jlpt: row.get(2)?,
+            frequency: row.get(3)?,
+            ..Default::default()
+        },
+        None => return Ok(None),
+    };
+
+    let mut stmt = connection.prepare(
+        "SELECT reading, type FROM kanji_dict_reading WHERE kanji = ? ORDER BY type, position",
+    )?;
+    let mut rows = stmt.query(params![kanji.to_string()])?;
+    while let Some(row) = rows.next()? {
+        let reading: String = row.get(0)?;
+        let kind: String = row.get(1)?;
+        match kind.as_str() {
+            "on" => info.on_readings.push(reading),
+            _ => info.kun_readings.push(reading),
+        }
+    }
+
+    let mut stmt =
+        connection.prepare("SELECT meaning FROM kanji_meaning WHERE kanji = ? ORDER BY position")?;
+    let meanings = stmt.query_map(params![kanji.to_string()], |row| row.get(0))?;
+    for meaning in meanings {
+        info.meanings.push(meaning?);
+    }
+    Ok(Some(info))
+}
+
+/// Store freshly imported radicals and components. Each table is only
+/// replaced when the file it comes from was imported (RADKFILE for radicals,
+/// KRADFILE for components), so importing the files in separate runs keeps
+/// the data of the earlier run.
+pub fn import_components(
+    radicals: &[(char, u8)],
+    components: &[(char, char)],
+    replace_radicals: bool,
+    replace_components: bool,
+) -> Result<(), CliError> {
+    let mut connection = connect()?;
+    let tx = connection.transaction()?;
+    if replace_radicals {
+        tx.execute("DELETE FROM radical", [])?;
+    }
+    if replace_components {
+        tx.execute("DELETE FROM kanji_component", [])?;
+    }
+    {
+        let mut radical =
+            tx.prepare("INSERT OR REPLACE INTO radical (radical, strokes) VALUES (?, ?)")?;
+        for (r, strokes) in radicals {
+            radical.execute((r.to_string(), strokes))?;
+        }
+        let mut component =
+            tx.prepare("INSERT OR IGNORE INTO kanji_component (kanji, component) VALUES (?, ?)")?;
+        for (kanji, c) in components {
+            component.execute((kanji.to_string(), c.to_string()))?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// All radicals, ordered by stroke count
+pub fn get_radicals() -> Result<Vec<(char, u8)>, CliError> {
+    let connection = connect()?;
+    let mut stmt =
+        connection.prepare("SELECT radical, strokes FROM radical ORDER BY strokes, radical")?;
+    let mut rows = stmt.query([])?;
+    let mut radicals = vec![];
+    while let Some(row) = rows.next()? {
+        let radical: String = row.get(0)?;
+        if let Some(radical) = radical.chars().next() {
+            radicals.push((radical, row.get(1)?));
+        }
+    }
+    Ok(radicals)
+}
+
+pub fn components_of(kanji: char) -> Result<Vec<char>, CliError> {
+    let connection = connect()?;
+    let mut stmt = connection.prepare(
+        "
+        SELECT c.component FROM kanji_component c
+        LEFT JOIN radical r ON r.radical = c.component
+        WHERE c.kanji = ? ORDER BY r.strokes
+    ",
+    )?;
+    let rows = stmt.query_map(params![kanji.to_string()], |row| row.get::<_, String>(0))?;
+    let mut components = vec![];
+    for component in rows {
+        components.extend(component?.chars().next());
+    }
+    Ok(components)
+}
+
+/// Known (level > 0) kanji that contain every one of the given components
+pub fn known_kanji_with_components(components: &[char]) -> Result<Vec<KanjiEntry>, CliError> {
+    let mut components = components.to_vec();
+    components.sort();
+    components.dedup();
+    let connection = connect()?;
+    let placeholders = vec!["?"; components.len()].join(", ");
+    let mut stmt = connection.prepare(&format!(
+        "
+        SELECT k.kanji, k.level FROM kanji k
+        WHERE k.level > 0 AND (
+            SELECT COUNT(*) FROM kanji_component c
+            WHERE c.kanji = k.kanji AND c.component IN ({})
+        ) = {}
+        ORDER BY k.level DESC
+    ",
+        placeholders,
+        components.len()
+    ))?;
+    let values = components.iter().map(|c| c.to_string());
+    let rows = stmt.query_map(params_from_iter(values), |row| {
+        Ok(KanjiEntry {
+            kanji: row.get(0)?,
+            level: row.get(1)?,
+        })
+    })?;
+    let mut kanji = vec![];
+    for entry in rows {
+        kanji.push(entry?);
+    }
+    Ok(kanji)
+}
+
+/// Replace the stored stroke paths of the given kanji
+pub fn import_strokes(kanji: &[(char, Vec<String>)]) -> Result<(), CliError> {
+    let mut connection = connect()?;
+    let tx = connection.transaction()?;
+    {
+        let mut delete = tx.prepare("DELETE FROM kanji_stroke WHERE kanji = ?")?;
+        let mut insert =
+            tx.prepare("INSERT INTO kanji_stroke (kanji, stroke, path) VALUES (?, ?, ?)")?;
+        for (k, strokes) in kanji {
+            delete.execute(params![k.to_string()])?;
+            for (i, path) in strokes.iter().enumerate() {
+                insert.execute((k.to_string(), i + 1, path))?;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// SVG path data of each stroke of a kanji, in stroke order
+pub fn get_strokes(kanji: char) -> Result<Vec<String>, CliError> {
+    let connection = connect()?;
+    let mut stmt =
+        connection.prepare("SELECT path FROM kanji_stroke WHERE kanji = ? ORDER BY stroke")?;
+    let rows = stmt.query_map(params![kanji.to_string()], |row| row.get(0))?;
+    let mut strokes = vec![];
+    for path in rows {
+        strokes.push(path?);
+    }
+    Ok(strokes)
+}
+
 pub fn get_all_kanji() -> Result<Vec<KanjiEntry>, CliError> {
     let connection = connect()?;
     let mut stmt = connection.prepare("SELECT kanji, level FROM kanji")?;
@@ -164,12 +1037,18 @@ pub fn kanji_count() -> Result<usize, CliError> {
     Ok(kanji.len())
 }
 
-fn connect() -> Result<Connection, CliError> {
+/// Open the database, upgrading its schema if needed
+pub fn connect() -> Result<Connection, CliError> {
     if let Some(proj_dirs) = ProjectDirs::from("com", "Immerse", "Immerse") {
         if !proj_dirs.data_dir().exists() {
             fs::create_dir_all(proj_dirs.data_dir())?;
         }
-        let connection = Connection::open(proj_dirs.data_dir().join("data.db"))?;
+        let path = proj_dirs.data_dir().join("data.db");
+        let mut connection = Connection::open(&path)?;
+        // The review server, texthooker and a sync may each hold a connection,
+        // so wait for another writer instead of failing straight away
+        connection.busy_timeout(std::time::Duration::from_secs(5))?;
+        migrate(&mut connection, &path)?;
         Ok(connection)
     } else {
         Err(CliError::Custom("Failed to locate db file!".to_string()))
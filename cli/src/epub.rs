@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::Path;
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use crate::CliError;
+
+/// A spine document of an EPUB, in reading order
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chapter {
+    pub title: String,
+    pub text: String,
+}
+
+/// Elements whose text is not part of the reading text. Ruby readings
+/// would otherwise count their kana, and repeat the kanji in rare cases.
+const SKIPPED: [&[u8]; 5] = [b"rt", b"rp", b"script", b"style", b"head"];
+
+/// Elements that end a line of text
+const BLOCKS: [&[u8]; 13] = [
+    b"p",
+    b"div",
+    b"br",
+    b"li",
+    b"tr",
+    b"h1",
+    b"h2",
+    b"h3",
+    b"h4",
+    b"h5",
+    b"h6",
+    b"section",
+    b"blockquote",
+];
+
+/// Read the chapters of an EPUB file in spine order
+pub fn read_epub(path: &Path) -> Result<Vec<Chapter>, CliError> {
+    let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+    let container = read_entry(&mut archive, "META-INF/container.xml")?;
+    let opf_path = rootfile(&container)?
+        .ok_or_else(|| CliError::Custom(format!("No package document in {}", path.display())))?;
+    let opf = read_entry(&mut archive, &opf_path)?;
+    let base = opf_path.rsplit_once('/').map_or("", |(dir, _)| dir);
+
+    let mut chapters = vec![];
+    for href in spine(&opf)? {
+        let entry = resolve(base, &href);
+        let xhtml = read_entry(&mut archive, &entry)?;
+        let (title, text) = chapter_text(&xhtml)?;
+        if text.trim().is_empty() {
+            continue;
+        }
+        chapters.push(Chapter {
+            title: title.unwrap_or(href),
+            text,
+        });
+    }
+    Ok(chapters)
+}
+
+fn read_entry<R: Read + Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    name: &str,
+) -> Result<String, CliError> {
+    let mut file = archive.by_name(name)?;
+    let mut text = String::new();
+    file.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+/// Path of the package document named in META-INF/container.xml
+pub fn rootfile(container: &str) -> Result<Option<String>, CliError> {
+    let mut reader = Reader::from_str(container);
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"rootfile" => {
+                if let Some(path) = attribute(&e, "full-path")? {
+                    return Ok(Some(path));
+                }
+            }
+            Event::Eof => return Ok(None),
+            _ => {}
+        }
+    }
+}
+
+/// Hrefs of the package's spine documents, in reading order
+pub fn spine(opf: &str) -> Result<Vec<String>, CliError> {
+    let mut reader = Reader::from_str(opf);
+    let mut manifest = HashMap::new();
+    let mut order = vec![];
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) => match e.local_name().as_ref() {
+                b"item" => {
+                    let media_type = attribute(&e, "media-type")?.unwrap_or_default();
+                    if let (Some(id), Some(href)) = (attribute(&e, "id")?, attribute(&e, "href")?) {
+                        if media_type.contains("html") {
+                            manifest.insert(id, href);
+                        }
+                    }
+                }
+                b"itemref" => {
+                    if let Some(id) = attribute(&e, "idref")? {
+                        order.push(id);
+                    }
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(order
+        .into_iter()
+        .filter_map(|id| manifest.get(&id).cloned())
+        .collect())
+}
+
+/// The title and reading text of an XHTML document. The title is its first
+/// heading, falling back to the <title> element.
+pub fn chapter_text(xhtml: &str) -> Result<(Option<String>, String), CliError> {
+    let mut reader = Reader::from_str(xhtml);
+    reader.config_mut().check_end_names = false;
+    let mut text = String::new();
+    let mut title = String::new();
+    let mut heading: Option<String> = None;
+    // Depth inside skipped elements, and whether we are in <title> or a heading
+    let mut skipped = 0;
+    let mut in_title = false;
+    let mut in_heading = false;
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => {
+                let name = e.local_name();
+                let name = name.as_ref();
+                if name == b"title" {
+                    in_title = true;
+                } else if SKIPPED.contains(&name) {
+                    skipped += 1;
+                } else if is_heading(name) && heading.is_none() {
+                    in_heading = true;
+                    heading = Some(String::new());
+                }
+                if BLOCKS.contains(&name) {
+                    text.push('\n');
+                }
+            }
+            Event::Empty(e) if BLOCKS.contains(&e.local_name().as_ref()) => {
+                text.push('\n');
+            }
+            Event::End(e) => {
+                let name = e.local_name();
+                let name = name.as_ref();
+                if name == b"title" {
+                    in_title = false;
+                } else if SKIPPED.contains(&name) {
+                    skipped -= 1;
+                } else if is_heading(name) {
+                    in_heading = false;
+                }
+                if BLOCKS.contains(&name) {
+                    text.push('\n');
+                }
+            }
+            Event::Text(t) => {
+                let value = t
+                    .unescape_with(|entity| match entity {
+                        "nbsp" => Some(" "),
+                        _ => None,
+                    })
+                    .map(|value| value.into_owned())
+                    .unwrap_or_else(|_| String::from_utf8_lossy(&t).into_owned());
+                if in_title {
+                    title.push_str(&value);
+                } else if skipped == 0 {
+                    text.push_str(&value);
+                    if in_heading {
+                        if let Some(heading) = heading.as_mut() {
+                            heading.push_str(&value);
+                        }
+                    }
+                }
+            }
+            Event::CData(t) if skipped == 0 => {
+                text.push_str(&String::from_utf8_lossy(&t));
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    let title = heading
+        .map(|heading| heading.trim().to_string())
+        .filter(|heading| !heading.is_empty())
+        .or_else(|| Some(title.trim().to_string()).filter(|title| !title.is_empty()));
+    Ok((title, text))
+}
+
+fn is_heading(name: &[u8]) -> bool {
+    matches!(name, b"h1" | b"h2" | b"h3")
+}
+
+fn attribute(e: &BytesStart, name: &str) -> Result<Option<String>, CliError> {
+    match e.try_get_attribute(name)? {
+        Some(attr) => Ok(Some(attr.unescape_value()?.into_owned())),
+        None => Ok(None),
+    }
+}
+
+/// Resolve a manifest href against the package document's directory.
+/// Hrefs are URLs, so fragments are dropped and escapes decoded.
+fn resolve(base: &str, href: &str) -> String {
+    let href = percent_decode(href.split('#').next().unwrap_or_default());
+    let mut parts: Vec<&str> = base.split('/').filter(|part| !part.is_empty()).collect();
+    for part in href.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = (bytes[i] == b'%')
+            .then(|| value.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escape {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+
+use crate::kanji::{is_kanji, reading_matches, to_hiragana};
+
+/// Dictionary readings of each kanji, as imported from KANJIDIC2
+pub type DictionaryReadings = HashMap<char, Vec<String>>;
+
+/// A run of field text, with the reading Anki's furigana syntax gave it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub text: String,
+    pub reading: Option<String>,
+}
+
+/// A kanji found in a field, with its own reading when the furigana could
+/// be split between the kanji of its word
+#[derive(Debug, Clone, PartialEq)]
+pub struct KanjiReading {
+    pub kanji: char,
+    pub reading: Option<String>,
+}
+
+/// The text of an Anki field value with markup removed
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedField {
+    pub segments: Vec<Segment>,
+}
+
+impl ParsedField {
+    /// Plain text, without readings
+    pub fn text(&self) -> String {
+        self.segments.iter().map(|s| s.text.as_str()).collect()
+    }
+
+    /// Reading of the whole text, if any of it had furigana
+    pub fn reading(&self) -> Option<String> {
+        self.segments.iter().any(|s| s.reading.is_some()).then(|| {
+            self.segments
+                .iter()
+                .map(|s| s.reading.as_deref().unwrap_or(&s.text))
+                .collect()
+        })
+    }
+
+    /// Every kanji in the field, in order. The reading of a run of several
+    /// kanji is split between them using their dictionary readings.
+    pub fn kanji(&self, dictionary: &DictionaryReadings) -> Vec<KanjiReading> {
+        let mut kanji = vec![];
+        for segment in &self.segments {
+            let chars: Vec<char> = segment.text.chars().collect();
+            let mut readings: Vec<Option<String>> = vec![None; chars.len()];
+            let runs = segment
+                .reading
+                .as_ref()
+                .and_then(|reading| align(&chars, &reading.chars().collect::<Vec<_>>()));
+            for (start, end, reading) in runs.into_iter().flatten() {
+                if end - start == 1 {
+                    readings[start] = Some(reading);
+                    continue;
+                }
+                let reading: Vec<char> = reading.chars().collect();
+                if let Some(parts) = split_run(&chars[start..end], &reading, dictionary) {
+                    for (i, part) in parts.into_iter().enumerate() {
+                        readings[start + i] = Some(part);
+                    }
+                }
+            }
+            for (c, reading) in chars.iter().zip(readings) {
+                if is_kanji(*c) {
+                    kanji.push(KanjiReading { kanji: *c, reading });
+                }
+            }
+        }
+        kanji
+    }
+}
+
+/// Parse a field value: strip HTML, sound tags and cloze markers, then split
+/// out `base[reading]` furigana
+pub fn parse_field(value: &str) -> ParsedField {
+    let text = decode_entities(&strip_tags(value));
+    let text = strip_sounds(&strip_clozes(&text));
+    ParsedField {
+        segments: furigana(&text),
+    }
+}
+
+fn strip_tags(value: &str) -> String {
+    let mut text = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('>') else {
+            // A lone '<' is text
+            text.push_str(&rest[start..]);
+            return text;
+        };
+        let tag = rest[start + 1..start + end]
+            .trim_start_matches('/')
+            .to_lowercase();
+        if ["br", "div", "p"]
+            .iter()
+            .any(|name| tag.split([' ', '/']).next() == Some(name))
+        {
+            text.push(' ');
+        }
+        rest = &rest[start + end + 1..];
+    }
+    text.push_str(rest);
+    text
+}
+
+fn decode_entities(value: &str) -> String {
+    let mut text = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest[1..]
+            .find(';')
+            .filter(|end| *end <= 10)
+            .map(|end| &rest[1..end + 1]);
+        let decoded = entity.and_then(|entity| match entity {
+            "nbsp" => Some(' '),
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => {
+                let code = match entity.strip_prefix("#x").or(entity.strip_prefix("#X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => entity.strip_prefix('#')?.parse().ok(),
+                };
+                char::from_u32(code?)
+            }
+        });
+        match (entity, decoded) {
+            (Some(entity), Some(c)) => {
+                text.push(c);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                text.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+/// Replace `{{c1::answer::hint}}` with its answer
+fn strip_clozes(value: &str) -> String {
+    let mut text = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("{{c") {
+        text.push_str(&rest[..start]);
+        let inner = &rest[start + 2..];
+        let (Some(separator), Some(end)) = (inner.find("::"), inner.find("}}")) else {
+            text.push_str(&rest[start..]);
+            return text;
+        };
+        if separator > end || !inner[1..separator].chars().all(|c| c.is_ascii_digit()) {
+            text.push_str("{{");
+            rest = inner;
+            continue;
+        }
+        let answer = &inner[separator + 2..end];
+        text.push_str(answer.split("::").next().unwrap_or_default());
+        rest = &inner[end + 2..];
+    }
+    text.push_str(rest);
+    text
+}
+
+/// Remove `[sound:file.mp3]` tags, which would otherwise read as furigana
+fn strip_sounds(value: &str) -> String {
+    let mut text = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("[sound:") {
+        text.push_str(&rest[..start]);
+        match rest[start..].find(']') {
+            Some(end) => rest = &rest[start + end + 1..],
+            None => {
+                rest = &rest[start..];
+                break;
+            }
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+/// Split Anki furigana syntax into segments. The base of a reading runs back
+/// to the previous space or reading, and a space before a base is only a
+/// separator.
+fn furigana(value: &str) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = vec![];
+    let mut pending = String::new();
+    let mut chars = value.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            ' ' => {
+                push_text(&mut segments, &mut pending);
+                pending.push(' ');
+                push_text(&mut segments, &mut pending);
+            }
+            '[' if !pending.is_empty() && value[i..].contains(']') => {
+                let end = i + value[i..].find(']').unwrap_or_default();
+                // Drop the separating space before this base
+                if segments
+                    .last()
+                    .is_some_and(|s| s.reading.is_none() && s.text == " ")
+                {
+                    segments.pop();
+                }
+                segments.push(Segment {
+                    text: std::mem::take(&mut pending),
+                    reading: Some(value[i + 1..end].trim().to_string()),
+                });
+                while chars.peek().is_some_and(|(j, _)| *j <= end) {
+                    chars.next();
+                }
+            }
+            _ => pending.push(c),
+        }
+    }
+    push_text(&mut segments, &mut pending);
+
+    // Merge neighbouring plain text
+    let mut merged: Vec<Segment> = vec![];
+    for segment in segments {
+        match merged.last_mut() {
+            Some(last) if last.reading.is_none() && segment.reading.is_none() => {
+                last.text.push_str(&segment.text)
+            }
+            _ => merged.push(segment),
+        }
+    }
+    merged
+}
+
+fn push_text(segments: &mut Vec<Segment>, pending: &mut String) {
+    if !pending.is_empty() {
+        segments.push(Segment {
+            text: std::mem::take(pending),
+            reading: None,
+        });
+    }
+}
+
+fn is_kanji_like(c: char) -> bool {
+    is_kanji(c) || c == '々'
+}
+
+/// Compare kana ignoring the hiragana/katakana distinction
+fn same_kana(a: char, b: char) -> bool {
+    to_hiragana(a) == to_hiragana(b)
+}
+
+/// Split the reading of a run of kanji between them so that each part is a
+/// form of one of its kanji's dictionary readings. 々 reads like the kanji
+/// before it. Returns None unless exactly one split fits.
+fn split_run(
+    run: &[char],
+    reading: &[char],
+    dictionary: &DictionaryReadings,
+) -> Option<Vec<String>> {
+    fn step(
+        run: &[char],
+        reading: &[char],
+        previous: Option<char>,
+        dictionary: &DictionaryReadings,
+        parts: &mut Vec<String>,
+        found: &mut Vec<Vec<String>>,
+    ) {
+        // A second split makes the run ambiguous, so stop looking
+        if found.len() > 1 {
+            return;
+        }
+        let Some(&first) = run.first() else {
+            if reading.is_empty() {
+                found.push(parts.clone());
+            }
+            return;
+        };
+        let kanji = match (first, previous) {
+            ('々', Some(previous)) => previous,
+            ('々', None) => return,
+            _ => first,
+        };
+        let Some(readings) = dictionary.get(&kanji) else {
+            return;
+        };
+        // Leave at least one kana for each remaining kanji
+        for taken in 1..=reading.len().saturating_sub(run.len() - 1) {
+            let part: String = reading[..taken].iter().collect();
+            if readings.iter().any(|r| reading_matches(r, &part)) {
+                parts.push(part);
+                step(
+                    &run[1..],
+                    &reading[taken..],
+                    Some(kanji),
+                    dictionary,
+                    parts,
+                    found,
+                );
+                parts.pop();
+            }
+        }
+    }
+
+    let mut found = vec![];
+    step(run, reading, None, dictionary, &mut vec![], &mut found);
+    if found.len() == 1 {
+        found.pop()
+    } else {
+        None
+    }
+}
+
+/// Match a word against its reading, pairing each run of kanji with the
+/// kana it is read as. Returns (start, end, reading) for each run, or None
+/// if the kana in the word don't line up with the reading.
+fn align(word: &[char], reading: &[char]) -> Option<Vec<(usize, usize, String)>> {
+    fn step(
+        word: &[char],
+        reading: &[char],
+        offset: usize,
+        runs: &mut Vec<(usize, usize, String)>,
+    ) -> bool {
+        let Some(&first) = word.first() else {
+            return reading.is_empty();
+        };
+        if !is_kanji_like(first) {
+            return reading.first().is_some_and(|&r| same_kana(first, r))
+                && step(&word[1..], &reading[1..], offset + 1, runs);
+        }
+        let len = word.iter().take_while(|c| is_kanji_like(**c)).count();
+        // Each kanji run takes at least one kana, trying the shortest first
+        for taken in 1..=reading.len() {
+            runs.push((offset, offset + len, reading[..taken].iter().collect()));
+            if step(&word[len..], &reading[taken..], offset + len, runs) {
+                return true;
+            }
+            runs.pop();
+        }
+        false
+    }
+
+    let mut runs = vec![];
+    step(word, reading, 0, &mut runs).then_some(runs)
+}
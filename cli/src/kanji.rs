@@ -1,5 +1,25 @@
+use std::fmt;
+
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::{Deserialize, Serialize};
+
 //Kanji Knowledge Levels
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    clap::ValueEnum,
+)]
 pub enum Knowledge {
+    #[default]
     None,
     Seen,
     Recognize,
@@ -7,10 +27,77 @@ pub enum Knowledge {
     Write,
     Master,
 }
-#[derive(Debug)]
+
+impl Knowledge {
+    /// Convert a numeric level as stored in the kanji table
+    pub fn from_level(level: i64) -> Option<Knowledge> {
+        match level {
+            0 => Some(Knowledge::None),
+            1 => Some(Knowledge::Seen),
+            2 => Some(Knowledge::Recognize),
+            3 => Some(Knowledge::Familiar),
+            4 => Some(Knowledge::Write),
+            5 => Some(Knowledge::Master),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Knowledge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+// Levels are stored as their ordinal so they can be compared in SQL
+impl ToSql for Knowledge {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(*self as i64))
+    }
+}
+
+impl FromSql for Knowledge {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value {
+            ValueRef::Null => Ok(Knowledge::None),
+            _ => {
+                let level = i64::column_result(value)?;
+                Knowledge::from_level(level).ok_or(FromSqlError::OutOfRange(level))
+            }
+        }
+    }
+}
+
+// Query parameters take the same names as the CLI and JSON bodies, in any case
+#[rocket::async_trait]
+impl<'v> rocket::form::FromFormField<'v> for Knowledge {
+    fn from_value(field: rocket::form::ValueField<'v>) -> rocket::form::Result<'v, Self> {
+        <Knowledge as clap::ValueEnum>::from_str(field.value, true).map_err(|_| {
+            rocket::form::Error::validation(format!("Unknown level {:?}", field.value)).into()
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub struct KanjiEntry {
     pub kanji: String,
-    pub level: u8,
+    pub level: Knowledge,
+}
+
+/// Dictionary data for a kanji, imported from KANJIDIC2
+#[derive(Debug, Default, Serialize)]
+pub struct KanjiInfo {
+    pub kanji: char,
+    pub on_readings: Vec<String>,
+    pub kun_readings: Vec<String>,
+    pub meanings: Vec<String>,
+    pub stroke_count: Option<u8>,
+    /// Jōyō grade (1-6 elementary, 8 secondary, 9-10 jinmeiyō)
+    pub grade: Option<u8>,
+    /// Pre-2010 JLPT level (1-4)
+    pub jlpt: Option<u8>,
+    /// Rank among the 2500 most used kanji in newspapers
+    pub frequency: Option<u16>,
 }
 
 pub fn is_kanji(c: char) -> bool {
@@ -24,14 +111,71 @@ pub fn is_kanji(c: char) -> bool {
         '\u{2CEB0}'..='\u{2EBEF}'
     )
 }
-pub fn recommended_level(interval: u16) -> u8 {
+pub fn recommended_level(interval: u16) -> Knowledge {
     if interval >= 100 {
-        3
+        Knowledge::Familiar
     } else if interval >= 60 {
-        2
+        Knowledge::Recognize
     } else if interval > 0 {
-        1
+        Knowledge::Seen
     } else {
-        0
+        Knowledge::None
+    }
+}
+
+/// Convert katakana to hiragana, leaving everything else alone
+pub fn to_hiragana(c: char) -> char {
+    match c {
+        '\u{30A1}'..='\u{30F6}' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+        _ => c,
+    }
+}
+
+/// The unvoiced kana for a voiced one, to undo rendaku
+fn unvoiced(c: char) -> char {
+    match c {
+        'が' | 'ぎ' | 'ぐ' | 'げ' | 'ご' | 'ざ' | 'じ' | 'ず' | 'ぜ' | 'ぞ' | 'だ' | 'ぢ' | 'づ' | 'で'
+        | 'ど' | 'ば' | 'び' | 'ぶ' | 'べ' | 'ぼ' => char::from_u32(c as u32 - 1).unwrap_or(c),
+        'ぱ' | 'ぴ' | 'ぷ' | 'ぺ' | 'ぽ' => char::from_u32(c as u32 - 2).unwrap_or(c),
+        _ => c,
+    }
+}
+
+/// The kana a KANJIDIC2 reading covers: hiragana, without the okurigana
+/// after '.' or the affix markers
+pub fn reading_stem(reading: &str) -> String {
+    reading
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| *c != '-')
+        .map(to_hiragana)
+        .collect()
+}
+
+/// Whether a reading met in vocabulary is a form of a dictionary reading,
+/// allowing for rendaku (人 as びと) and gemination (学 as がっ)
+pub fn reading_matches(dictionary: &str, met: &str) -> bool {
+    let dictionary: Vec<char> = reading_stem(dictionary).chars().collect();
+    let met: Vec<char> = met.chars().map(to_hiragana).collect();
+    if dictionary.is_empty() || met.is_empty() {
+        return false;
+    }
+    let same = |a: &[char], b: &[char]| {
+        a.len() == b.len()
+            && a.iter()
+                .zip(b)
+                .enumerate()
+                .all(|(i, (x, y))| x == y || (i == 0 && unvoiced(*x) == unvoiced(*y)))
+    };
+    if same(&dictionary, &met) {
+        return true;
+    }
+    match (met.split_last(), dictionary.split_last()) {
+        (Some(('っ', met)), Some((last, dictionary))) => {
+            matches!(last, 'く' | 'つ' | 'ち' | 'き') && same(dictionary, met)
+        }
+        _ => false,
     }
 }
@@ -0,0 +1,80 @@
+use std::io::BufRead;
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use crate::kanji::{is_kanji, KanjiInfo};
+use crate::CliError;
+
+/// Parse the characters of a KANJIDIC2 XML file.
+/// Only Japanese readings and English meanings are kept.
+pub fn parse_kanjidic<R: BufRead>(source: R) -> Result<Vec<KanjiInfo>, CliError> {
+    let mut reader = Reader::from_reader(source);
+    reader.config_mut().trim_text(true);
+
+    let mut entries = vec![];
+    let mut current: Option<KanjiInfo> = None;
+    let mut tag: Vec<u8> = vec![];
+    // r_type of the reading element, or m_lang of the meaning element
+    let mut kind: Option<String> = None;
+    let mut buf = vec![];
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => {
+                if e.name().as_ref() == b"character" {
+                    current = Some(KanjiInfo::default());
+                }
+                kind = match e.name().as_ref() {
+                    b"reading" => attribute(&e, b"r_type")?,
+                    b"meaning" => attribute(&e, b"m_lang")?,
+                    _ => None,
+                };
+                tag = e.name().as_ref().to_vec();
+            }
+            Event::Text(t) => {
+                let Some(info) = current.as_mut() else {
+                    continue;
+                };
+                let text = t.unescape()?.into_owned();
+                match tag.as_slice() {
+                    b"literal" => info.kanji = text.chars().next().unwrap_or_default(),
+                    // Later stroke counts are common miscounts
+                    b"stroke_count" if info.stroke_count.is_none() => {
+                        info.stroke_count = text.parse().ok()
+                    }
+                    b"grade" => info.grade = text.parse().ok(),
+                    b"jlpt" => info.jlpt = text.parse().ok(),
+                    b"freq" => info.frequency = text.parse().ok(),
+                    b"reading" => match kind.as_deref() {
+                        Some("ja_on") => info.on_readings.push(text),
+                        Some("ja_kun") => info.kun_readings.push(text),
+                        _ => {}
+                    },
+                    b"meaning" if kind.is_none() => info.meanings.push(text),
+                    _ => {}
+                }
+            }
+            Event::End(e) => {
+                if e.name().as_ref() == b"character" {
+                    if let Some(info) = current.take() {
+                        if is_kanji(info.kanji) {
+                            entries.push(info);
+                        }
+                    }
+                }
+                tag.clear();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(entries)
+}
+
+fn attribute(e: &BytesStart, name: &[u8]) -> Result<Option<String>, CliError> {
+    match e.try_get_attribute(name).map_err(quick_xml::Error::from)? {
+        Some(attr) => Ok(Some(attr.unescape_value()?.into_owned())),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,188 @@
+use std::path::Path;
+
+use quick_xml::events::Event;
+use quick_xml::Reader;
+
+use crate::kanji::is_kanji;
+use crate::CliError;
+
+/// Points sampled along each cubic bezier segment
+const CURVE_STEPS: usize = 8;
+
+/// The kanji a KanjiVG file describes. Files are named after the
+/// codepoint (`04e9c.svg`); variants such as `04e9c-Kaisho.svg` are skipped.
+pub fn file_kanji(path: &Path) -> Option<char> {
+    if path.extension()? != "svg" {
+        return None;
+    }
+    let stem = path.file_stem()?.to_str()?;
+    if stem.contains('-') {
+        return None;
+    }
+    let kanji = char::from_u32(u32::from_str_radix(stem, 16).ok()?)?;
+    is_kanji(kanji).then_some(kanji)
+}
+
+/// Path data of each stroke, in stroke order
+pub fn parse_strokes(svg: &str) -> Result<Vec<String>, CliError> {
+    let mut reader = Reader::from_str(svg);
+    let mut strokes = vec![];
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"path" => {
+                if let Some(d) = e.try_get_attribute("d").map_err(quick_xml::Error::from)? {
+                    strokes.push(d.unescape_value()?.into_owned());
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(strokes)
+}
+
+enum Token {
+    Command(char),
+    Number(f64),
+}
+
+fn tokenize(d: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let chars: Vec<char> = d.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_ascii_alphabetic() {
+            tokens.push(Token::Command(c));
+            i += 1;
+        } else if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' {
+            // Numbers may run together, as in "1.5-2" or "1.5.5"
+            let start = i;
+            let mut seen_dot = false;
+            i += 1;
+            if c == '.' {
+                seen_dot = true;
+            }
+            while i < chars.len() {
+                match chars[i] {
+                    '0'..='9' => i += 1,
+                    '.' if !seen_dot => {
+                        seen_dot = true;
+                        i += 1
+                    }
+                    _ => break,
+                }
+            }
+            let number: String = chars[start..i].iter().collect();
+            if let Ok(n) = number.parse() {
+                tokens.push(Token::Number(n));
+            }
+        } else {
+            i += 1;
+        }
+    }
+    tokens
+}
+
+fn cubic(p0: (f64, f64), p1: (f64, f64), p2: (f64, f64), p3: (f64, f64), t: f64) -> (f64, f64) {
+    let u = 1.0 - t;
+    (
+        u * u * u * p0.0 + 3.0 * u * u * t * p1.0 + 3.0 * u * t * t * p2.0 + t * t * t * p3.0,
+        u * u * u * p0.1 + 3.0 * u * u * t * p1.1 + 3.0 * u * t * t * p2.1 + t * t * t * p3.1,
+    )
+}
+
+/// Flatten SVG path data into a polyline, in KanjiVG's 109x109 coordinate space
+pub fn stroke_points(d: &str) -> Vec<(f64, f64)> {
+    let tokens = tokenize(d);
+    let mut points = vec![];
+    let mut pos = (0.0, 0.0);
+    let mut start = (0.0, 0.0);
+    // Second control point of the last curve, for smooth (S) curves
+    let mut last_control: Option<(f64, f64)> = None;
+    let mut command = 'M';
+    let mut i = 0;
+
+    let number = |i: &mut usize| -> Option<f64> {
+        match tokens.get(*i) {
+            Some(Token::Number(n)) => {
+                *i += 1;
+                Some(*n)
+            }
+            _ => None,
+        }
+    };
+
+    while i < tokens.len() {
+        if let Token::Command(c) = tokens[i] {
+            command = c;
+            i += 1;
+            if c == 'Z' || c == 'z' {
+                pos = start;
+                points.push(pos);
+                last_control = None;
+            }
+            continue;
+        }
+        let relative = command.is_ascii_lowercase();
+        let offset = if relative { pos } else { (0.0, 0.0) };
+        match command.to_ascii_uppercase() {
+            'M' | 'L' => {
+                let (Some(x), Some(y)) = (number(&mut i), number(&mut i)) else {
+                    break;
+                };
+                pos = (offset.0 + x, offset.1 + y);
+                if command.to_ascii_uppercase() == 'M' {
+                    start = pos;
+                    // Further coordinate pairs after a move are lines
+                    command = if relative { 'l' } else { 'L' };
+                }
+                points.push(pos);
+                last_control = None;
+            }
+            'H' => {
+                let Some(x) = number(&mut i) else { break };
+                pos.0 = if relative { pos.0 + x } else { x };
+                points.push(pos);
+                last_control = None;
+            }
+            'V' => {
+                let Some(y) = number(&mut i) else { break };
+                pos.1 = if relative { pos.1 + y } else { y };
+                points.push(pos);
+                last_control = None;
+            }
+            'C' | 'S' => {
+                let c1 = if command.to_ascii_uppercase() == 'C' {
+                    let (Some(x), Some(y)) = (number(&mut i), number(&mut i)) else {
+                        break;
+                    };
+                    (offset.0 + x, offset.1 + y)
+                } else {
+                    match last_control {
+                        Some(c) => (2.0 * pos.0 - c.0, 2.0 * pos.1 - c.1),
+                        None => pos,
+                    }
+                };
+                let (Some(x2), Some(y2), Some(x), Some(y)) = (
+                    number(&mut i),
+                    number(&mut i),
+                    number(&mut i),
+                    number(&mut i),
+                ) else {
+                    break;
+                };
+                let c2 = (offset.0 + x2, offset.1 + y2);
+                let end = (offset.0 + x, offset.1 + y);
+                for step in 1..=CURVE_STEPS {
+                    points.push(cubic(pos, c1, c2, end, step as f64 / CURVE_STEPS as f64));
+                }
+                pos = end;
+                last_control = Some(c2);
+            }
+            // Unsupported command, skip its arguments
+            _ => i += 1,
+        }
+    }
+    points
+}
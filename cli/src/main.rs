@@ -1,7 +1,20 @@
+mod analyze;
 mod ankiconnect;
+mod apkg;
+mod api;
 mod config;
 mod db;
+mod epub;
+mod fields;
 mod kanji;
+mod kanjidic;
+mod kanjivg;
+mod migrate;
+mod optimize;
+mod radicals;
+mod review;
+mod subtitles;
+mod sync;
 mod tui;
 mod rocket;
 #[cfg(test)]
@@ -18,24 +31,23 @@ use db::due_cards;
 use db::wipe_srs_db;
 use rocket::rocket;
 use clap::Subcommand;
-use config::add_deck;
+use config::{add_deck, set_note_field, DeckConfig};
 use config::read_config;
-use db::ensure_card_db;
-use db::KanjiSrs;
-use fsrs::Card;
-use kanji::is_kanji;
-use kanji::recommended_level;
+use config::set_fsrs_params;
+use kanji::Knowledge;
+use review::CanHaveReview;
 use std::fmt;
 use std::fs;
 use std::io;
+use std::path::{Path, PathBuf};
 use tui::CanHaveKanjiList;
+use tui::CanHaveRadicalSearch;
 use tui::CanHaveSelection;
-//Use Directories crate to get app data dir
-use directories::ProjectDirs;
+use tui::CanHaveStrokeOrder;
 //Using Clap to parse CLI calls
 use clap::Parser;
 //Using Rusqlite as an sqlite handler
-use rusqlite::{Connection, Result};
+use rusqlite::Result;
 //Use Colored for prettier text
 use colored::Colorize;
 
@@ -63,6 +75,16 @@ impl fmt::Display for CliError {
             CliError::JSON(ref err) => write!(f, "An error occurred: {}", err.to_string().red()),
             CliError::BSON(ref err) => write!(f, "An error occurred: {}", err.to_string().red()),
             CliError::Rocket(ref err) => write!(f, "An error occurred: {}", err.to_string().red()),
+            CliError::XML(ref err) => write!(f, "An error occurred: {}", err.to_string().red()),
+            CliError::Zip(ref err) => write!(f, "An error occurred: {}", err.to_string().red()),
+            CliError::AnkiKeyRejected => write!(
+                f,
+                "An error occurred: {}",
+                "AnkiConnect rejected the API key. Set ankiconnect.api_key in config.json to the apiKey configured in AnkiConnect".red()
+            ),
+            CliError::AnkiConnect(ref err) => {
+                write!(f, "AnkiConnect returned an error: {}", err.red())
+            }
         }
     }
 }
@@ -76,6 +98,10 @@ pub enum CliError {
     JSON(serde_json::Error),
     BSON(bson::de::Error),
     Rocket(::rocket::Error),
+    XML(quick_xml::Error),
+    Zip(zip::result::ZipError),
+    AnkiKeyRejected,
+    AnkiConnect(String),
 }
 
 impl From<rusqlite::Error> for CliError {
@@ -113,6 +139,18 @@ impl From<::rocket::Error> for CliError {
     }
 }
 
+impl From<quick_xml::Error> for CliError {
+    fn from(err: quick_xml::Error) -> Self {
+        CliError::XML(err)
+    }
+}
+
+impl From<zip::result::ZipError> for CliError {
+    fn from(err: zip::result::ZipError) -> Self {
+        CliError::Zip(err)
+    }
+}
+
 /*
 Command type defs
 */
@@ -128,12 +166,109 @@ struct Args {
 enum Commands {
     AnkiConnectTest,
     DBRead,
-    AnkiSync,
+    /// Sync kanji from an Anki deck, through AnkiConnect or from a collection file
+    AnkiSync {
+        /// Read an .apkg, .colpkg or collection.anki2 file instead of using AnkiConnect
+        #[arg(long)]
+        file: Option<PathBuf>,
+        /// Deck to sync, instead of choosing one interactively. May be repeated.
+        #[arg(long = "deck")]
+        decks: Vec<String>,
+        /// Sync every deck in config.json without prompting
+        #[arg(long, conflicts_with = "decks")]
+        all_configured: bool,
+    },
     GetDBKanji,
     KanjiCount,
     Rocket,
     WipeDB,
     ListNewCards,
+    /// List kanji whose Anki cards have all been deleted
+    OrphanedKanji,
+    Review,
+    OptimizeParams,
+    /// Import meanings, readings and stroke counts from a KANJIDIC2 XML file
+    ImportKanjidic {
+        path: PathBuf,
+    },
+    /// Show dictionary information for a kanji
+    KanjiInfo {
+        kanji: char,
+    },
+    /// Measure how much of a text's kanji are known
+    Analyze {
+        path: PathBuf,
+        /// How many of the most frequent unknown kanji to list
+        #[arg(long, default_value_t = 20)]
+        top: usize,
+    },
+    /// Rank the subtitle files in a directory by their share of unknown kanji
+    RankSubtitles {
+        dir: PathBuf,
+    },
+    /// Show where an EPUB introduces unknown kanji, chapter by chapter
+    BookDensity {
+        path: PathBuf,
+        /// Chapter to start from; kanji of earlier chapters count as already met.
+        /// Defaults to the chapter after the last one recorded with ReadBook
+        #[arg(long)]
+        from: Option<usize>,
+        /// How many new kanji to list per chapter
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+    },
+    /// Record chapters of an EPUB as read, counting their kanji as exposure
+    ReadBook {
+        path: PathBuf,
+        /// Last chapter read; defaults to the chapter after the last one recorded
+        #[arg(long)]
+        through: Option<usize>,
+    },
+    /// Suggest unknown kanji to learn next from how often they were met in immersion text
+    Next {
+        /// How many kanji to suggest
+        #[arg(long, default_value_t = 10)]
+        count: usize,
+        /// Add the suggestions to the srs as new cards
+        #[arg(long)]
+        add: bool,
+    },
+    /// List vocabulary words synced from Anki that have been learned
+    Words,
+    /// List the known words containing a kanji
+    KanjiWords {
+        kanji: char,
+    },
+    /// Show the readings of a kanji met in synced vocabulary, and those still unknown
+    KanjiReadings {
+        kanji: char,
+    },
+    /// Import kanji components from KRADFILE and/or RADKFILE
+    ImportRadicals {
+        #[arg(long)]
+        kradfile: Option<PathBuf>,
+        #[arg(long)]
+        radkfile: Option<PathBuf>,
+    },
+    /// List known kanji containing all of the given radicals, or pick them interactively
+    RadicalSearch {
+        radicals: Vec<String>,
+    },
+    /// Import stroke data from a directory of KanjiVG SVG files
+    #[command(name = "import-kanjivg")]
+    ImportKanjiVG {
+        dir: PathBuf,
+    },
+    /// Animate the stroke order of a kanji
+    StrokeOrder {
+        kanji: char,
+    },
+    /// Correct the knowledge level of a kanji by hand
+    SetLevel {
+        kanji: char,
+        #[arg(value_enum)]
+        level: Knowledge,
+    },
 }
 
 /*
@@ -150,7 +285,11 @@ async fn main() {
         Commands::DBRead => {
             db_read();
         }
-        Commands::AnkiSync => match anki_sync().await {
+        Commands::AnkiSync {
+            file,
+            decks,
+            all_configured,
+        } => match anki_sync(file, decks, all_configured).await {
             Ok(_) => {}
             Err(ref err) => eprintln!("{}", err),
         },
@@ -162,8 +301,15 @@ async fn main() {
             Ok(_) => {}
             Err(ref err) => eprintln!("{}", err),
         },
-        Commands::Rocket => {
-            if let Ok(_) = rocket().launch().await {};
+        Commands::Rocket => match crate::db::connect() {
+            Ok(_) => {
+                if let Ok(_) = rocket().launch().await {};
+            }
+            Err(ref err) => eprintln!("{}", err),
+        },
+        Commands::OrphanedKanji => match orphaned_kanji().await {
+            Ok(_) => {}
+            Err(ref err) => eprintln!("{}", err),
         },
         Commands::WipeDB => match wipe_srs_db() {
             Ok(_) => {println!("SRS Data Wiped!")},
@@ -177,6 +323,76 @@ async fn main() {
             },
             Err(ref err) => eprintln!("{}", err),
         },
+        Commands::Review => match review().await {
+            Ok(_) => {}
+            Err(ref err) => eprintln!("{}", err),
+        },
+        Commands::OptimizeParams => match optimize_params() {
+            Ok(_) => {}
+            Err(ref err) => eprintln!("{}", err),
+        },
+        Commands::ImportKanjidic { path } => match import_kanjidic(path) {
+            Ok(_) => {}
+            Err(ref err) => eprintln!("{}", err),
+        },
+        Commands::KanjiInfo { kanji } => match kanji_info(kanji) {
+            Ok(_) => {}
+            Err(ref err) => eprintln!("{}", err),
+        },
+        Commands::Analyze { path, top } => match analyze(path, top) {
+            Ok(_) => {}
+            Err(ref err) => eprintln!("{}", err),
+        },
+        Commands::RankSubtitles { dir } => match rank_subtitles(dir) {
+            Ok(_) => {}
+            Err(ref err) => eprintln!("{}", err),
+        },
+        Commands::BookDensity { path, from, top } => match book_density(path, from, top) {
+            Ok(_) => {}
+            Err(ref err) => eprintln!("{}", err),
+        },
+        Commands::ReadBook { path, through } => match read_book(path, through) {
+            Ok(_) => {}
+            Err(ref err) => eprintln!("{}", err),
+        },
+        Commands::Next { count, add } => match next_kanji(count, add) {
+            Ok(_) => {}
+            Err(ref err) => eprintln!("{}", err),
+        },
+        Commands::Words => match known_words() {
+            Ok(_) => {}
+            Err(ref err) => eprintln!("{}", err),
+        },
+        Commands::KanjiWords { kanji } => match kanji_words(kanji) {
+            Ok(_) => {}
+            Err(ref err) => eprintln!("{}", err),
+        },
+        Commands::KanjiReadings { kanji } => match kanji_readings(kanji) {
+            Ok(_) => {}
+            Err(ref err) => eprintln!("{}", err),
+        },
+        Commands::ImportRadicals { kradfile, radkfile } => {
+            match import_radicals(kradfile, radkfile) {
+                Ok(_) => {}
+                Err(ref err) => eprintln!("{}", err),
+            }
+        }
+        Commands::RadicalSearch { radicals } => match radical_search(radicals).await {
+            Ok(_) => {}
+            Err(ref err) => eprintln!("{}", err),
+        },
+        Commands::ImportKanjiVG { dir } => match import_kanjivg(dir) {
+            Ok(_) => {}
+            Err(ref err) => eprintln!("{}", err),
+        },
+        Commands::StrokeOrder { kanji } => match stroke_order(kanji).await {
+            Ok(_) => {}
+            Err(ref err) => eprintln!("{}", err),
+        },
+        Commands::SetLevel { kanji, level } => match set_level(kanji, level) {
+            Ok(_) => {}
+            Err(ref err) => eprintln!("{}", err),
+        },
     }
 }
 /*
@@ -191,87 +407,587 @@ async fn get_db_kanji() -> Result<(), CliError> {
     Ok(())
 }
 
-fn kanji_count() -> Result<(), CliError> {
-    let len = crate::db::kanji_count()?;
+async fn orphaned_kanji() -> Result<(), CliError> {
+    let kanji = crate::db::orphaned_kanji()?;
+    if kanji.is_empty() {
+        println!("Every kanji still has a source card in Anki");
+        return Ok(());
+    }
+    let mut terminal = crate::tui::init()?;
+    terminal.kanji_list(kanji, "Kanji Without Anki Cards").await?;
+    Ok(())
+}
+
+async fn review() -> Result<(), CliError> {
+    let cards = due_cards()?;
+    if cards.is_empty() {
+        println!("No cards are due for review!");
+        return Ok(());
+    }
+    let mut terminal = crate::tui::init()?;
+    let reviewed = terminal.review_session(cards).await?;
     println!(
-        "Found {} entries",
-        Colorize::green(len.to_string().as_str())
+        "Reviewed {} cards",
+        Colorize::green(reviewed.to_string().as_str())
     );
     Ok(())
 }
 
-async fn anki_sync() -> Result<(), CliError> {
-    //Get list of anki decks
-    let decklist = get_decks().await?.result;
+fn optimize_params() -> Result<(), CliError> {
+    let logs = crate::db::review_logs()?;
+    println!("Optimising FSRS parameters from {} reviews...", logs.len());
+    let report = crate::optimize::optimize(&logs, crate::review::parameters()?)?;
+    println!(
+        "Scored on the {} most recent reviews, held out from training",
+        report.after.count
+    );
+    println!(
+        "Log loss: {:.4} -> {}",
+        report.before.log_loss,
+        Colorize::green(format!("{:.4}", report.after.log_loss).as_str())
+    );
+    println!(
+        "RMSE:     {:.4} -> {}",
+        report.before.rmse,
+        Colorize::green(format!("{:.4}", report.after.rmse).as_str())
+    );
+    set_fsrs_params(report.weights)?;
+    println!("Saved personalised parameters to config.json");
+    Ok(())
+}
 
-    //Ask user to select deck
+fn import_kanjidic(path: PathBuf) -> Result<(), CliError> {
+    println!("Reading {}...", path.display());
+    let file = io::BufReader::new(fs::File::open(path)?);
+    let entries = crate::kanjidic::parse_kanjidic(file)?;
+    crate::db::import_kanji_info(&entries)?;
+    println!(
+        "Imported {} kanji",
+        Colorize::green(entries.len().to_string().as_str())
+    );
+    Ok(())
+}
+
+fn kanji_info(kanji: char) -> Result<(), CliError> {
+    let info = match crate::db::get_kanji_info(kanji)? {
+        Some(info) => info,
+        None => {
+            return Err(CliError::Custom(format!(
+                "No dictionary entry for {}, has KANJIDIC2 been imported?",
+                kanji
+            )))
+        }
+    };
+    println!("{}", Colorize::bold(kanji.to_string().as_str()));
+    println!("Meanings: {}", info.meanings.join(", "));
+    println!("On:       {}", info.on_readings.join("、"));
+    println!("Kun:      {}", info.kun_readings.join("、"));
+    if let Some(strokes) = info.stroke_count {
+        println!("Strokes:  {}", strokes);
+    }
+    if let Some(grade) = info.grade {
+        println!("Grade:    {}", grade);
+    }
+    if let Some(jlpt) = info.jlpt {
+        println!("JLPT:     {} (pre-2010 level)", jlpt);
+    }
+    if let Some(frequency) = info.frequency {
+        println!("Frequency rank: {}", frequency);
+    }
+    let components = crate::db::components_of(kanji)?;
+    if !components.is_empty() {
+        let components: Vec<String> = components.iter().map(|c| c.to_string()).collect();
+        println!("Components: {}", components.join(" "));
+    }
+    Ok(())
+}
+
+fn analyze(path: PathBuf, top: usize) -> Result<(), CliError> {
+    let text = crate::analyze::read_document(&path)?;
+    let coverage = crate::analyze::KanjiCounts::from_text(&text)
+        .coverage(&crate::analyze::kanji_levels()?);
+    if coverage.tokens == 0 {
+        println!("No kanji found in {}", path.display());
+        return Ok(());
+    }
+    println!(
+        "{} kanji, {} unique",
+        Colorize::green(coverage.tokens.to_string().as_str()),
+        Colorize::green(coverage.unique.to_string().as_str())
+    );
+    for share in coverage.levels.iter().rev() {
+        println!(
+            "{:<10} {:>5.1}% of kanji  {:>5.1}% of unique",
+            share.level.to_string(),
+            share.tokens as f64 * 100.0 / coverage.tokens as f64,
+            share.unique as f64 * 100.0 / coverage.unique as f64
+        );
+    }
+    if !coverage.unknown.is_empty() {
+        let unknown: Vec<String> = coverage
+            .unknown
+            .iter()
+            .take(top)
+            .map(|(kanji, count)| format!("{} ({})", kanji, count))
+            .collect();
+        println!("Most frequent unknown: {}", unknown.join(" "));
+    }
+    println!(
+        "Estimated comprehension: {}, {}",
+        Colorize::green(format!("{:.1}%", coverage.comprehension * 100.0).as_str()),
+        coverage.verdict()
+    );
+    Ok(())
+}
+
+/// Easiest episodes first
+fn rank_subtitles(dir: PathBuf) -> Result<(), CliError> {
+    let levels = crate::analyze::kanji_levels()?;
+    let mut episodes = vec![];
+    for entry in std::fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if crate::subtitles::SubtitleFormat::from_path(&path).is_none() {
+            continue;
+        }
+        let text = match crate::analyze::read_document(&path) {
+            Ok(text) => text,
+            Err(err) => {
+                eprintln!("Skipping {}: {}", path.display(), err);
+                continue;
+            }
+        };
+        let coverage = crate::analyze::KanjiCounts::from_text(&text).coverage(&levels);
+        episodes.push((path, coverage));
+    }
+    if episodes.is_empty() {
+        println!("No SRT, ASS or WebVTT files found in {}", dir.display());
+        return Ok(());
+    }
+    episodes.sort_by(|a, b| {
+        a.1.unknown_share()
+            .total_cmp(&b.1.unknown_share())
+            .then_with(|| a.0.cmp(&b.0))
+    });
+    for (path, coverage) in episodes {
+        let unknown: usize = coverage.unknown.iter().map(|(_, count)| count).sum();
+        println!(
+            "{}  {} of {} kanji unknown ({} of {} unique)  {}",
+            Colorize::green(format!("{:>5.1}%", coverage.unknown_share() * 100.0).as_str()),
+            unknown,
+            coverage.tokens,
+            coverage.unknown.len(),
+            coverage.unique,
+            path.file_name().unwrap_or_default().to_string_lossy()
+        );
+    }
+    Ok(())
+}
+
+/// Books are tracked by their absolute path
+fn book_key(path: &Path) -> Result<String, CliError> {
+    Ok(fs::canonicalize(path)?.display().to_string())
+}
+
+fn book_density(path: PathBuf, from: Option<usize>, top: usize) -> Result<(), CliError> {
+    let chapters = crate::epub::read_epub(&path)?;
+    let read = match from {
+        Some(from) => from.saturating_sub(1),
+        None => crate::db::book_progress(&book_key(&path)?)?.unwrap_or_default(),
+    };
+    if read >= chapters.len() {
+        println!("{} has {} chapters", path.display(), chapters.len());
+        return Ok(());
+    }
+    let texts: Vec<&str> = chapters.iter().map(|chapter| chapter.text.as_str()).collect();
+    let densities =
+        crate::analyze::new_kanji_density(&texts, &crate::analyze::kanji_levels()?, read);
+    for (i, (chapter, density)) in chapters[read..].iter().zip(&densities).enumerate() {
+        let new: String = density.new.iter().take(top).map(|(kanji, _)| kanji).collect();
+        println!(
+            "{:>3}. {}  {} new ({:.1} per 1000 kanji)  {:.1}% comprehension  {}",
+            read + i + 1,
+            chapter.title,
+            Colorize::green(density.new.len().to_string().as_str()),
+            density.per_thousand(),
+            density.coverage.comprehension * 100.0,
+            new
+        );
+    }
+    Ok(())
+}
+
+fn read_book(path: PathBuf, through: Option<usize>) -> Result<(), CliError> {
+    let chapters = crate::epub::read_epub(&path)?;
+    let book = book_key(&path)?;
+    let read = crate::db::book_progress(&book)?.unwrap_or_default();
+    let through = through.unwrap_or(read + 1).min(chapters.len());
+    if through <= read {
+        println!("Already read through chapter {} of {}", read, chapters.len());
+        return Ok(());
+    }
+    let text: Vec<&str> = chapters[read..through]
+        .iter()
+        .map(|chapter| chapter.text.as_str())
+        .collect();
+    let promote_after = read_config()?.exposure.promote_after;
+    let promoted = crate::db::record_book_reading(
+        &book,
+        through,
+        &text.join("\n"),
+        chrono::Utc::now().timestamp(),
+        promote_after,
+    )?;
+    println!(
+        "Recorded chapters {} to {} of {} as read",
+        read + 1,
+        through,
+        chapters.len()
+    );
+    if !promoted.is_empty() {
+        let promoted: String = promoted.into_iter().collect();
+        println!("Promoted to Seen: {}", Colorize::green(promoted.as_str()));
+    }
+    Ok(())
+}
+
+fn next_kanji(count: usize, add: bool) -> Result<(), CliError> {
+    let exposure = crate::db::exposure_counts()?;
+    if exposure.is_empty() {
+        println!("No exposure recorded yet; send text to /ws/hook or record chapters with read-book");
+        return Ok(());
+    }
+    let queued = crate::db::srs_kanji()?.into_iter().collect();
+    let recommendations = crate::analyze::recommend(
+        &exposure,
+        &crate::db::learned_kanji()?,
+        &crate::db::all_components()?,
+        &queued,
+    );
+    let recommendations = &recommendations[..count.min(recommendations.len())];
+    if recommendations.is_empty() {
+        println!("Every kanji met so far is known or already queued");
+        return Ok(());
+    }
+    for (i, recommendation) in recommendations.iter().enumerate() {
+        let known: String = recommendation.known_components.iter().collect();
+        println!(
+            "{:>3}. {}  met {} times  +{:.2}% coverage  {}/{} components known {}",
+            i + 1,
+            Colorize::green(recommendation.kanji.to_string().as_str()),
+            recommendation.count,
+            recommendation.gain * 100.0,
+            recommendation.known_components.len(),
+            recommendation.components.len(),
+            known
+        );
+    }
+    if add {
+        let kanji: Vec<char> = recommendations.iter().map(|r| r.kanji).collect();
+        let added = crate::db::add_new_cards(&kanji)?;
+        println!("Added {} new cards", Colorize::green(added.to_string().as_str()));
+    }
+    Ok(())
+}
+
+fn print_word(word: &crate::db::Word) {
+    println!(
+        "{}  {}  {} ({}d)",
+        Colorize::green(word.word.as_str()),
+        word.reading.as_deref().unwrap_or_default(),
+        word.deck,
+        word.interval
+    );
+}
+
+fn known_words() -> Result<(), CliError> {
+    let words = crate::db::known_words()?;
+    for word in &words {
+        print_word(word);
+    }
+    println!("Found {} known words", Colorize::green(words.len().to_string().as_str()));
+    Ok(())
+}
+
+fn kanji_words(kanji: char) -> Result<(), CliError> {
+    let words = crate::db::words_with_kanji(kanji)?;
+    if words.is_empty() {
+        println!("No known words contain {}", kanji);
+        return Ok(());
+    }
+    for word in &words {
+        print_word(word);
+    }
+    Ok(())
+}
+
+fn kanji_readings(kanji: char) -> Result<(), CliError> {
+    let met = crate::db::met_readings(kanji)?;
+    println!("{}", Colorize::bold(kanji.to_string().as_str()));
+    println!("Met:     {}", met.join("、"));
+    let Some(info) = crate::db::get_kanji_info(kanji)? else {
+        return Ok(());
+    };
+    let unknown = |readings: &[String]| -> Vec<String> {
+        readings
+            .iter()
+            .filter(|reading| !met.iter().any(|m| crate::kanji::reading_matches(reading, m)))
+            .cloned()
+            .collect()
+    };
+    println!(
+        "Unknown: {}",
+        Colorize::yellow(
+            [unknown(&info.on_readings), unknown(&info.kun_readings)]
+                .concat()
+                .join("、")
+                .as_str()
+        )
+    );
+    Ok(())
+}
+
+fn import_radicals(kradfile: Option<PathBuf>, radkfile: Option<PathBuf>) -> Result<(), CliError> {
+    if kradfile.is_none() && radkfile.is_none() {
+        return Err(CliError::Custom(
+            "Pass --kradfile and/or --radkfile".to_string(),
+        ));
+    }
+    let mut radicals: Vec<(char, u8)> = vec![];
+    let mut components: Vec<(char, char)> = vec![];
+    let (has_radkfile, has_kradfile) = (radkfile.is_some(), kradfile.is_some());
+    if let Some(path) = radkfile {
+        let text = crate::radicals::read_dictionary_file(&path)?;
+        for radical in crate::radicals::parse_radkfile(&text) {
+            radicals.push((radical.radical, radical.strokes));
+            components.extend(radical.kanji.iter().map(|kanji| (*kanji, radical.radical)));
+        }
+    }
+    if let Some(path) = kradfile {
+        let text = crate::radicals::read_dictionary_file(&path)?;
+        for (kanji, parts) in crate::radicals::parse_kradfile(&text) {
+            components.extend(parts.iter().map(|part| (kanji, *part)));
+        }
+    }
+    crate::db::import_components(&radicals, &components, has_radkfile, has_kradfile)?;
+    println!(
+        "Imported {} radicals and {} kanji components",
+        Colorize::green(radicals.len().to_string().as_str()),
+        Colorize::green(components.len().to_string().as_str())
+    );
+    Ok(())
+}
+
+fn import_kanjivg(dir: PathBuf) -> Result<(), CliError> {
+    let mut kanji = vec![];
+    for file in fs::read_dir(dir)? {
+        let path = file?.path();
+        if let Some(k) = crate::kanjivg::file_kanji(&path) {
+            let strokes = crate::kanjivg::parse_strokes(&fs::read_to_string(&path)?)?;
+            kanji.push((k, strokes));
+        }
+    }
+    crate::db::import_strokes(&kanji)?;
+    println!(
+        "Imported stroke order for {} kanji",
+        Colorize::green(kanji.len().to_string().as_str())
+    );
+    Ok(())
+}
+
+async fn stroke_order(kanji: char) -> Result<(), CliError> {
+    let strokes = crate::db::get_strokes(kanji)?;
+    if strokes.is_empty() {
+        return Err(CliError::Custom(format!(
+            "No stroke data for {}, has KanjiVG been imported?",
+            kanji
+        )));
+    }
     let mut terminal = crate::tui::init()?;
-    let selection = terminal.selection_list(decklist, "Select A Deck:").await?;
+    terminal.stroke_order(kanji, strokes).await?;
+    crate::tui::restore()?;
+    Ok(())
+}
+
+async fn radical_search(radicals: Vec<String>) -> Result<(), CliError> {
+    let radicals: Vec<char> = radicals
+        .iter()
+        .flat_map(|r| r.chars())
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    if radicals.is_empty() {
+        let all = crate::db::get_radicals()?;
+        if all.is_empty() {
+            return Err(CliError::Custom(
+                "No radicals found, run ImportRadicals first".to_string(),
+            ));
+        }
+        let mut terminal = crate::tui::init()?;
+        terminal.radical_search(all).await?;
+        return Ok(());
+    }
+    let matches = crate::db::known_kanji_with_components(&radicals)?;
+    for entry in matches.iter() {
+        println!("{} ({})", entry.kanji, entry.level);
+    }
+    println!(
+        "Found {} known kanji",
+        Colorize::green(matches.len().to_string().as_str())
+    );
+    Ok(())
+}
 
-    //Pull the content of all cards in the deck
-    println!("Getting cards in deck...");
-    let result = get_cards(selection.clone()).await?;
-    println!("Getting card content...");
-    let info = get_card_content(result.result).await?;
+fn set_level(kanji: char, level: Knowledge) -> Result<(), CliError> {
+    crate::db::set_kanji_level(kanji, level)?;
+    println!(
+        "Set {} to {}",
+        kanji,
+        Colorize::green(level.to_string().as_str())
+    );
+    Ok(())
+}
 
-    //Track cards that we can't process
-    let mut skipped = 0;
+fn kanji_count() -> Result<(), CliError> {
+    let len = crate::db::kanji_count()?;
+    println!(
+        "Found {} entries",
+        Colorize::green(len.to_string().as_str())
+    );
+    Ok(())
+}
 
-    // Check if we have a stored config field to parse words from.
-    //If not, ask user to select one and then save to config.
+async fn anki_sync(
+    file: Option<PathBuf>,
+    decks: Vec<String>,
+    all_configured: bool,
+) -> Result<(), CliError> {
+    let collection = match file {
+        Some(path) => Some(crate::apkg::Collection::open(&path)?),
+        None => None,
+    };
 
-    let config = read_config()?;
-    let mut field: String = String::new();
-    if let Some(deck) = config.decks.into_iter().find(|deck| deck.name == selection) {
-        field = deck.word_field
+    let selection = if all_configured {
+        let configured: Vec<String> = read_config()?.decks.into_iter().map(|d| d.name).collect();
+        if configured.is_empty() {
+            return Err(CliError::Custom("No decks are configured".to_string()));
+        }
+        configured
+    } else if !decks.is_empty() {
+        decks
     } else {
-        terminal = crate::tui::init()?;
-        let nf = terminal
-            .selection_list(
-                info.result[0].fields.keys().cloned().collect(),
-                "Choose a field to parse word from:",
-            )
-            .await?;
-        add_deck(selection, nf.clone())?;
-        field = nf;
-    }
-
-    //Amount of cards in deck for later processed count
-    let len = info.result.len();
-
-    //Make  sure the SRS table exists before we write to it
-    ensure_card_db()?;
-
-
-    // Add the kanji to both the srs and kanji tables
-    for card in info.result {
-        let word = card.fields.get(&field);
-        match word {
-            Some(word) => {
-                for kanji in word.value.chars() {
-                    if !is_kanji(kanji) {break;}
-                    match crate::db::add_kanji(kanji, recommended_level(card.interval)) {
-                        Ok(_) => {}
-                        Err(ref err) => println!("{}", err),
+        //Get list of anki decks
+        let decklist = match &collection {
+            Some(collection) => collection.decks(),
+            None => get_decks().await?,
+        };
+
+        //Ask user to select deck
+        let mut terminal = crate::tui::init()?;
+        vec![terminal.selection_list(decklist, "Select A Deck:").await?]
+    };
+
+    for name in selection {
+        // Check if we have a stored config field to parse words from.
+        //If not, ask user to select one from a sample card and then save to config.
+        let config = read_config()?;
+        let deck = match config.decks.into_iter().find(|deck| deck.name == name) {
+            Some(deck) => deck,
+            None => {
+                let sample = match &collection {
+                    Some(collection) => collection.deck_cards(&name).into_iter().next(),
+                    None => {
+                        let ids = get_cards(&name).await?;
+                        get_card_content(&ids[..ids.len().min(1)])
+                            .await?
+                            .into_iter()
+                            .next()
                     }
-                    let srscard = Card::new();
-                    crate::db::card_to_db(KanjiSrs {
-                        kanji,
-                        card: srscard
-                    })?;
-                }
+                };
+                let Some(sample) = sample else {
+                    eprintln!("No cards found in deck {}, skipping it", name);
+                    continue;
+                };
+                let mut terminal = crate::tui::init()?;
+                let nf = terminal
+                    .selection_list(
+                        sample.fields.keys().cloned().collect(),
+                        "Choose a field to parse word from:",
+                    )
+                    .await?;
+                let deck = DeckConfig::new(name.clone(), nf);
+                add_deck(deck.clone())?;
+                deck
             }
-            None => {
-                skipped += 1;
+        };
+
+        println!("Syncing cards in deck {}...", name);
+        let mut report = sync_deck_from(&collection, &deck).await?;
+
+        // Note types without the deck's word field get their own mapping,
+        // after which the deck is synced again
+        let unmapped: Vec<_> = report
+            .unmapped
+            .iter()
+            .filter(|(_, fields)| !fields.is_empty())
+            .collect();
+        if !all_configured && !unmapped.is_empty() {
+            for (model, fields) in unmapped {
+                let mut terminal = crate::tui::init()?;
+                let field = terminal
+                    .selection_list(
+                        fields.clone(),
+                        &format!("Choose the word field for {} notes:", model),
+                    )
+                    .await?;
+                set_note_field(&name, model.clone(), field)?;
+            }
+            let config = read_config()?;
+            if let Some(deck) = config.decks.into_iter().find(|deck| deck.name == name) {
+                report = sync_deck_from(&collection, &deck).await?;
             }
         }
+        print_sync_report(&report);
     }
-    println!("Skipped {} cards that couldn't be processed", skipped);
+    Ok(())
+}
+
+/// Sync a deck from the collection file when one was given, or else through AnkiConnect
+async fn sync_deck_from(
+    collection: &Option<crate::apkg::Collection>,
+    deck: &DeckConfig,
+) -> Result<crate::sync::SyncReport, CliError> {
+    match collection {
+        Some(collection) => crate::sync::sync_collection(collection, deck),
+        None => crate::sync::sync_deck(deck).await,
+    }
+}
+
+fn print_sync_report(report: &crate::sync::SyncReport) {
     println!(
-        "Synced {} entries",
-        Colorize::green((len - skipped).to_string().as_str())
+        "Skipped {} cards that couldn't be processed",
+        report.skipped
     );
-    Ok(())
+    println!(
+        "Synced {} new or changed entries, removed {}, updating {} kanji",
+        Colorize::green(report.synced.to_string().as_str()),
+        Colorize::green(report.removed.to_string().as_str()),
+        Colorize::green(report.kanji.to_string().as_str())
+    );
+    println!(
+        "Seeded review state for {} kanji from Anki history",
+        Colorize::green(report.seeded.to_string().as_str())
+    );
+    for model in report.unmapped.keys() {
+        println!(
+            "Skipped {} notes without a word field mapping",
+            Colorize::yellow(model.as_str())
+        );
+    }
+    if report.orphaned > 0 {
+        println!(
+            "{} kanji no longer have any Anki cards, see OrphanedKanji",
+            Colorize::yellow(report.orphaned.to_string().as_str())
+        );
+    }
 }
 
 async fn anki_connect_test() {
@@ -289,24 +1005,7 @@ fn db_read() {
     }
 }
 
-/*
-TODO: Move this
-*/
-
 fn read_db() -> Result<&'static str, CliError> {
-    if let Some(proj_dirs) = ProjectDirs::from("com", "Immerse", "Immerse") {
-        if !proj_dirs.data_dir().exists() {
-            fs::create_dir_all(proj_dirs.data_dir())?;
-        }
-        let connection = Connection::open(proj_dirs.data_dir().join("data.db"))?;
-        connection.execute(
-            "CREATE TABLE IF NOT EXISTS kanji (
-            kanji TEXT NOT NULL
-        )",
-            [],
-        )?;
-        Ok("Successfully located Database file!")
-    } else {
-        Err(CliError::Custom("Failed to locate db file!".to_string()))
-    }
+    crate::db::connect()?;
+    Ok("Successfully located Database file!")
 }
@@ -0,0 +1,296 @@
+use std::path::Path;
+
+use chrono::Utc;
+use rusqlite::{Connection, Transaction};
+
+use crate::CliError;
+
+type Migration = fn(&Transaction) -> rusqlite::Result<()>;
+
+/// Schema upgrades, in order. A database's `PRAGMA user_version` is the
+/// number of these that have been applied, so steps must only ever be appended.
+const MIGRATIONS: &[Migration] = &[
+    baseline,
+    sync_sources,
+    kanji_readings,
+    words,
+    exposure,
+    manual_levels,
+    skipped_cards,
+    split_readings,
+    book_progress,
+];
+
+/// Bring a database up to the current schema, backing it up first if it
+/// already holds data
+pub fn migrate(connection: &mut Connection, path: &Path) -> Result<(), CliError> {
+    let version = user_version(connection)?;
+    if version >= MIGRATIONS.len() {
+        return Ok(());
+    }
+    let tables: usize =
+        connection.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| row.get(0))?;
+    if tables > 0 {
+        backup(connection, path, version)?;
+    }
+    apply(connection)
+}
+
+/// Run every migration newer than the database's user_version
+pub fn apply(connection: &mut Connection) -> Result<(), CliError> {
+    let version = user_version(connection)?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = connection.transaction()?;
+        migration(&tx)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+pub fn user_version(connection: &Connection) -> Result<usize, CliError> {
+    Ok(connection.pragma_query_value(None, "user_version", |row| row.get(0))?)
+}
+
+fn backup(connection: &Connection, path: &Path, version: usize) -> Result<(), CliError> {
+    let name = format!(
+        "{}.v{}-{}.bak",
+        path.file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("data.db"),
+        version,
+        Utc::now().format("%Y%m%d%H%M%S")
+    );
+    let backup = path.with_file_name(name);
+    connection.execute("VACUUM INTO ?", [backup.to_string_lossy().into_owned()])?;
+    Ok(())
+}
+
+fn has_column(tx: &Transaction, table: &str, column: &str) -> rusqlite::Result<bool> {
+    let mut stmt = tx.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns = stmt.query_map([], |row| row.get::<_, String>(1))?;
+    for name in columns {
+        if name? == column {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Tables as they were created ad hoc before versioning. The kanji table is
+/// rebuilt since older versions created it without a level or primary key.
+fn baseline(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS kanji (
+            kanji TEXT NOT NULL
+        )",
+    )?;
+    let level = if has_column(tx, "kanji", "level")? {
+        "MAX(COALESCE(level, 0))"
+    } else {
+        "0"
+    };
+    tx.execute_batch(&format!(
+        "
+        CREATE TABLE kanji_v1 (
+            kanji TEXT NOT NULL PRIMARY KEY,
+            level INTEGER NOT NULL DEFAULT 0
+        );
+        INSERT INTO kanji_v1 (kanji, level)
+            SELECT kanji, MIN(MAX({}, 0), 5) FROM kanji GROUP BY kanji;
+        DROP TABLE kanji;
+        ALTER TABLE kanji_v1 RENAME TO kanji;
+        ",
+        level
+    ))?;
+
+    tx.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS srs (
+            kanji TEXT NOT NULL PRIMARY KEY,
+            card BLOB,
+            due INTEGER,
+            status INTEGER
+        );
+        CREATE TABLE IF NOT EXISTS review_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            kanji TEXT NOT NULL,
+            rating INTEGER NOT NULL,
+            reviewed INTEGER NOT NULL,
+            elapsed_days INTEGER NOT NULL,
+            scheduled_days INTEGER NOT NULL,
+            state_before INTEGER NOT NULL,
+            state_after INTEGER NOT NULL,
+            duration INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS review_log_kanji ON review_log (kanji, reviewed);
+        CREATE TABLE IF NOT EXISTS kanji_info (
+            kanji TEXT NOT NULL PRIMARY KEY,
+            stroke_count INTEGER,
+            grade INTEGER,
+            jlpt INTEGER,
+            frequency INTEGER
+        );
+        CREATE TABLE IF NOT EXISTS kanji_dict_reading (
+            kanji TEXT NOT NULL,
+            reading TEXT NOT NULL,
+            type TEXT NOT NULL,
+            position INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS kanji_dict_reading_kanji ON kanji_dict_reading (kanji);
+        CREATE TABLE IF NOT EXISTS kanji_meaning (
+            kanji TEXT NOT NULL,
+            meaning TEXT NOT NULL,
+            position INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS kanji_meaning_kanji ON kanji_meaning (kanji);
+        CREATE TABLE IF NOT EXISTS radical (
+            radical TEXT NOT NULL PRIMARY KEY,
+            strokes INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS kanji_component (
+            kanji TEXT NOT NULL,
+            component TEXT NOT NULL,
+            PRIMARY KEY (kanji, component)
+        );
+        CREATE INDEX IF NOT EXISTS kanji_component_component ON kanji_component (component);
+        CREATE TABLE IF NOT EXISTS kanji_stroke (
+            kanji TEXT NOT NULL,
+            stroke INTEGER NOT NULL,
+            path TEXT NOT NULL,
+            PRIMARY KEY (kanji, stroke)
+        );
+        ",
+    )
+}
+
+/// Anki cards backing each kanji and per-deck checkpoints, for incremental sync
+fn sync_sources(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+        CREATE TABLE anki_card (
+            card_id INTEGER NOT NULL PRIMARY KEY,
+            deck TEXT NOT NULL,
+            word TEXT NOT NULL,
+            interval INTEGER NOT NULL,
+            modified INTEGER NOT NULL
+        );
+        CREATE INDEX anki_card_deck ON anki_card (deck);
+        CREATE TABLE sync_checkpoint (
+            deck TEXT NOT NULL PRIMARY KEY,
+            field TEXT NOT NULL,
+            synced_at INTEGER NOT NULL
+        );
+        ALTER TABLE kanji ADD COLUMN orphaned INTEGER NOT NULL DEFAULT 0;
+        ",
+    )
+}
+
+/// Readings of each kanji met in synced vocabulary, by source card
+fn kanji_readings(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+        CREATE TABLE kanji_reading (
+            card_id INTEGER NOT NULL,
+            kanji TEXT NOT NULL,
+            reading TEXT NOT NULL,
+            PRIMARY KEY (card_id, kanji, reading)
+        );
+        CREATE INDEX kanji_reading_kanji ON kanji_reading (kanji);
+        -- Cards synced before now have no readings, so make the next sync a full one
+        DELETE FROM sync_checkpoint;
+        ",
+    )
+}
+
+/// Source cards become vocabulary words, keeping the parsed word and its
+/// reading, note and kanji
+fn words(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+        DROP INDEX anki_card_deck;
+        ALTER TABLE anki_card RENAME TO words;
+        ALTER TABLE words ADD COLUMN note_id INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE words ADD COLUMN reading TEXT;
+        CREATE INDEX words_deck ON words (deck);
+        CREATE TABLE word_kanji (
+            card_id INTEGER NOT NULL,
+            kanji TEXT NOT NULL,
+            PRIMARY KEY (card_id, kanji)
+        );
+        CREATE INDEX word_kanji_kanji ON word_kanji (kanji);
+        -- Stored words are raw field values until re-read, so make the next sync a full one
+        DELETE FROM sync_checkpoint;
+        ",
+    )
+}
+
+/// Lines of text received from a texthooker, and how often and when each
+/// kanji was last met in them
+fn exposure(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+        CREATE TABLE hook_line (
+            id INTEGER NOT NULL PRIMARY KEY,
+            line TEXT NOT NULL,
+            received_at INTEGER NOT NULL
+        );
+        CREATE TABLE kanji_exposure (
+            kanji TEXT NOT NULL PRIMARY KEY,
+            count INTEGER NOT NULL,
+            last_seen INTEGER NOT NULL
+        );
+        ",
+    )
+}
+
+/// Mark levels set by hand so syncs stop overwriting them. Syncs never set a
+/// level above Familiar, so higher levels must have been set by hand.
+fn manual_levels(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+        ALTER TABLE kanji ADD COLUMN manual INTEGER NOT NULL DEFAULT 0;
+        UPDATE kanji SET manual = 1 WHERE level > 3;
+        ",
+    )
+}
+
+/// Cards skipped for lacking a word field, so incremental syncs stop
+/// fetching them every time
+fn skipped_cards(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+        CREATE TABLE skipped_card (
+            card_id INTEGER NOT NULL PRIMARY KEY,
+            deck TEXT NOT NULL,
+            model TEXT NOT NULL,
+            fields TEXT NOT NULL,
+            modified INTEGER NOT NULL
+        );
+        CREATE INDEX skipped_card_deck ON skipped_card (deck);
+        ",
+    )
+}
+
+/// Readings of kanji in multi-kanji words are now split using KANJIDIC2
+fn split_readings(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+        -- Words synced before now only have readings for single kanji, so make the next sync a full one
+        DELETE FROM sync_checkpoint;
+        ",
+    )
+}
+
+/// How far each book has been read, by chapter
+fn book_progress(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+        CREATE TABLE book_progress (
+            book TEXT NOT NULL PRIMARY KEY,
+            chapter INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL
+        );
+        ",
+    )
+}
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use fsrs::{Card, Parameters, Rating, FSRS};
+
+use crate::{db::KanjiReviewLog, CliError};
+
+// Forgetting curve constants used by FSRS
+const DECAY: f64 = -0.5;
+const FACTOR: f64 = 19.0 / 81.0;
+
+/// Reviews needed before optimisation is attempted
+pub const MIN_REVIEWS: usize = 64;
+
+const ROUNDS: usize = 12;
+const BINS: usize = 20;
+
+/// Share of the review history, by date, held out to score the weights on
+const HOLDOUT: f64 = 0.2;
+
+/// Range of each weight. rs-fsrs doesn't bound its parameters, so these are
+/// the ones the reference FSRS optimizer clips to; outside them the model
+/// can schedule nonsense such as negative stability.
+const BOUNDS: [(f64, f64); 19] = [
+    (0.01, 100.0),
+    (0.01, 100.0),
+    (0.01, 100.0),
+    (0.01, 100.0),
+    (1.0, 10.0),
+    (0.001, 4.0),
+    (0.001, 4.0),
+    (0.001, 0.75),
+    (0.0, 4.5),
+    (0.0, 0.8),
+    (0.001, 3.5),
+    (0.001, 5.0),
+    (0.001, 0.25),
+    (0.001, 0.9),
+    (0.0, 4.0),
+    (0.0, 1.0),
+    (1.0, 6.0),
+    (0.0, 2.0),
+    (0.0, 2.0),
+];
+
+/// How well a set of parameters predicts the recorded review outcomes
+#[derive(Debug, Clone, Copy)]
+pub struct Metrics {
+    pub log_loss: f64,
+    pub rmse: f64,
+    /// Number of reviews the prediction was scored on
+    pub count: usize,
+}
+
+/// Metrics before and after are both scored on the held out reviews, which
+/// the weights weren't fitted on
+pub struct OptimizeReport {
+    pub before: Metrics,
+    pub after: Metrics,
+    pub weights: Vec<f64>,
+}
+
+/// Probability of recall after `elapsed_days` for a memory of the given stability
+pub fn retrievability(elapsed_days: f64, stability: f64) -> f64 {
+    (1.0 + FACTOR * elapsed_days / stability).powf(DECAY)
+}
+
+/// Group review logs into per-kanji histories, oldest review first
+fn histories(logs: &[KanjiReviewLog]) -> Vec<Vec<&KanjiReviewLog>> {
+    let mut grouped: HashMap<char, Vec<&KanjiReviewLog>> = HashMap::new();
+    for log in logs {
+        grouped.entry(log.kanji).or_default().push(log);
+    }
+    grouped
+        .into_values()
+        .map(|mut history| {
+            history.sort_by_key(|log| log.log.reviewed_date);
+            history
+        })
+        .collect()
+}
+
+/// Replay every history with the given parameters, scoring each review that
+/// happened at least a day after the previous one. Only reviews on the
+/// `held_out` side of `cutoff` are scored, though earlier ones still shape
+/// the card's state.
+fn evaluate(
+    histories: &[Vec<&KanjiReviewLog>],
+    params: Parameters,
+    cutoff: DateTime<Utc>,
+    held_out: bool,
+) -> Metrics {
+    let fsrs = FSRS::new(params);
+    let mut predictions: Vec<(f64, f64)> = vec![];
+    for history in histories {
+        let mut card = Card::new();
+        let mut last_review: Option<DateTime<Utc>> = None;
+        for entry in history {
+            let now = entry.log.reviewed_date;
+            if let Some(last) = last_review {
+                let elapsed = (now - last).num_seconds() as f64 / 86400.0;
+                if elapsed >= 1.0 && card.stability > 0.0 && (now >= cutoff) == held_out {
+                    let recalled = if entry.log.rating == Rating::Again { 0.0 } else { 1.0 };
+                    predictions.push((retrievability(elapsed, card.stability), recalled));
+                }
+            }
+            card = match fsrs.repeat(card.clone(), now).remove(&entry.log.rating) {
+                Some(info) => info.card,
+                None => card,
+            };
+            last_review = Some(now);
+        }
+    }
+    metrics(&predictions)
+}
+
+/// Log-loss and binned RMSE of (predicted, actual) recall pairs
+fn metrics(predictions: &[(f64, f64)]) -> Metrics {
+    if predictions.is_empty() {
+        return Metrics {
+            log_loss: 0.0,
+            rmse: 0.0,
+            count: 0,
+        };
+    }
+    let mut log_loss = 0.0;
+    let mut bins = [(0.0, 0.0, 0usize); BINS];
+    for &(p, y) in predictions {
+        let p = p.clamp(0.0001, 0.9999);
+        log_loss -= y * p.ln() + (1.0 - y) * (1.0 - p).ln();
+        let bin = ((p * BINS as f64) as usize).min(BINS - 1);
+        bins[bin].0 += p;
+        bins[bin].1 += y;
+        bins[bin].2 += 1;
+    }
+    let count = predictions.len();
+    let squared: f64 = bins
+        .iter()
+        .filter(|(_, _, n)| *n > 0)
+        .map(|(p, y, n)| (p - y).powi(2) / *n as f64)
+        .sum();
+    Metrics {
+        log_loss: log_loss / count as f64,
+        rmse: (squared / count as f64).sqrt(),
+        count,
+    }
+}
+
+pub fn with_weights(weights: &[f64]) -> Result<Parameters, CliError> {
+    let mut params = Parameters::default();
+    let expected = params.w.len();
+    params.w = weights.try_into().map_err(|_| {
+        CliError::Custom(format!(
+            "Expected {} FSRS parameters but found {}",
+            expected,
+            weights.len()
+        ))
+    })?;
+    Ok(params)
+}
+
+/// Train FSRS weights on the review history, starting from `initial`.
+/// Uses coordinate descent on the log-loss of the predicted recall probability
+/// over the older reviews, then scores the result on the most recent ones.
+pub fn optimize(logs: &[KanjiReviewLog], initial: Parameters) -> Result<OptimizeReport, CliError> {
+    let histories = histories(logs);
+    let mut dates: Vec<DateTime<Utc>> = logs.iter().map(|log| log.log.reviewed_date).collect();
+    dates.sort();
+    let Some(&cutoff) = dates.get((dates.len() as f64 * (1.0 - HOLDOUT)) as usize) else {
+        return Err(CliError::Custom(
+            "No review history to optimise".to_string(),
+        ));
+    };
+    let start = evaluate(&histories, initial, cutoff, false);
+    let before = evaluate(&histories, initial, cutoff, true);
+    if start.count < MIN_REVIEWS || before.count == 0 {
+        return Err(CliError::Custom(format!(
+            "Not enough review history to optimise: {} usable reviews to train on and {} \
+            to validate with, need at least {} and 1",
+            start.count, before.count, MIN_REVIEWS
+        )));
+    }
+
+    let mut weights = initial.w.to_vec();
+    let mut best = start;
+    let mut step = 0.2;
+    for _ in 0..ROUNDS {
+        for i in 0..weights.len() {
+            let delta = step * weights[i].abs().max(0.1);
+            for candidate in [weights[i] + delta, weights[i] - delta] {
+                let mut trial = weights.clone();
+                let (min, max) = BOUNDS.get(i).copied().unwrap_or((0.0, 100.0));
+                trial[i] = candidate.clamp(min, max);
+                let result = evaluate(&histories, with_weights(&trial)?, cutoff, false);
+                if result.log_loss < best.log_loss {
+                    best = result;
+                    weights = trial;
+                    break;
+                }
+            }
+        }
+        step /= 2.0;
+    }
+
+    let params = with_weights(&weights)?;
+    Ok(OptimizeReport {
+        before,
+        after: evaluate(&histories, params, cutoff, true),
+        weights,
+    })
+}
@@ -0,0 +1,72 @@
+use std::fs;
+use std::path::Path;
+
+use crate::CliError;
+
+/// A RADKFILE radical and the kanji it appears in
+#[derive(Debug)]
+pub struct Radical {
+    pub radical: char,
+    pub strokes: u8,
+    pub kanji: Vec<char>,
+}
+
+/// Read a KRADFILE/RADKFILE, which are distributed as EUC-JP but also
+/// commonly converted to UTF-8
+pub fn read_dictionary_file(path: &Path) -> Result<String, CliError> {
+    let bytes = fs::read(path)?;
+    match String::from_utf8(bytes) {
+        Ok(text) => Ok(text),
+        Err(err) => {
+            let (text, _, had_errors) = encoding_rs::EUC_JP.decode(err.as_bytes());
+            if had_errors {
+                return Err(CliError::Custom(format!(
+                    "{} is neither UTF-8 nor EUC-JP",
+                    path.display()
+                )));
+            }
+            Ok(text.into_owned())
+        }
+    }
+}
+
+/// Parse KRADFILE lines of the form `亜 : ｜ 一 口`
+pub fn parse_kradfile(text: &str) -> Vec<(char, Vec<char>)> {
+    text.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let (kanji, components) = line.split_once(':')?;
+            let kanji = kanji.trim().chars().next()?;
+            let components = components
+                .split_whitespace()
+                .filter_map(|c| c.chars().next())
+                .collect();
+            Some((kanji, components))
+        })
+        .collect()
+}
+
+/// Parse RADKFILE, where each `$ <radical> <strokes>` line is followed by
+/// lines listing the kanji containing that radical
+pub fn parse_radkfile(text: &str) -> Vec<Radical> {
+    let mut radicals: Vec<Radical> = vec![];
+    for line in text.lines().filter(|line| !line.starts_with('#')) {
+        if let Some(header) = line.strip_prefix('$') {
+            let mut parts = header.split_whitespace();
+            let radical = parts.next().and_then(|r| r.chars().next());
+            let strokes = parts.next().and_then(|s| s.parse().ok());
+            if let (Some(radical), Some(strokes)) = (radical, strokes) {
+                radicals.push(Radical {
+                    radical,
+                    strokes,
+                    kanji: vec![],
+                });
+            }
+        } else if let Some(current) = radicals.last_mut() {
+            current
+                .kanji
+                .extend(line.chars().filter(|c| !c.is_whitespace()));
+        }
+    }
+    radicals
+}
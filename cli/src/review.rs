@@ -0,0 +1,246 @@
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use fsrs::{Parameters, Rating, RecordLog, ReviewLog, FSRS};
+use ratatui::{
+    crossterm::event::{self, KeyCode, KeyEventKind},
+    layout::{Alignment, Constraint, Direction, Layout},
+    style::{palette::tailwind, Modifier, Style, Stylize},
+    text::{Line, Text},
+    widgets::{
+        block::{Position, Title},
+        Block, Borders, Paragraph,
+    },
+};
+
+use crate::{
+    config::read_config,
+    db::{card_to_db, get_kanji_info, log_review, KanjiReviewLog, KanjiSrs},
+    kanji::KanjiInfo,
+    optimize::with_weights,
+    tui::Tui,
+    CliError,
+};
+
+/// Ratings in the order they are offered to the user
+pub const RATINGS: [Rating; 4] = [Rating::Again, Rating::Hard, Rating::Good, Rating::Easy];
+
+/// Map a number key (1-4) to its FSRS rating
+pub fn rating_from_key(c: char) -> Option<Rating> {
+    match c {
+        '1' => Some(Rating::Again),
+        '2' => Some(Rating::Hard),
+        '3' => Some(Rating::Good),
+        '4' => Some(Rating::Easy),
+        _ => None,
+    }
+}
+
+pub fn rating_name(rating: Rating) -> &'static str {
+    match rating {
+        Rating::Again => "Again",
+        Rating::Hard => "Hard",
+        Rating::Good => "Good",
+        Rating::Easy => "Easy",
+    }
+}
+
+/// FSRS parameters to schedule with, using the personalised weights from
+/// the config when OptimizeParams has been run
+pub fn parameters() -> Result<Parameters, CliError> {
+    match read_config()?.fsrs_params {
+        Some(weights) => with_weights(&weights),
+        None => Ok(Parameters::default()),
+    }
+}
+
+pub fn scheduler() -> Result<FSRS, CliError> {
+    Ok(FSRS::new(parameters()?))
+}
+
+/// Every possible outcome of reviewing a card right now
+pub fn preview(fsrs: &FSRS, card: &KanjiSrs, now: DateTime<Utc>) -> RecordLog {
+    fsrs.repeat(card.card.clone(), now)
+}
+
+/// Apply a rating to a card with FSRS scheduling, returning the updated card
+/// and the log entry describing the review
+pub fn schedule(
+    fsrs: &FSRS,
+    card: KanjiSrs,
+    rating: Rating,
+    now: DateTime<Utc>,
+) -> Result<(KanjiSrs, ReviewLog), CliError> {
+    let mut outcomes = preview(fsrs, &card, now);
+    let info = outcomes
+        .remove(&rating)
+        .ok_or_else(|| CliError::Custom("FSRS returned no schedule for rating".to_string()))?;
+    Ok((
+        KanjiSrs {
+            kanji: card.kanji,
+            card: info.card,
+        },
+        info.review_log,
+    ))
+}
+
+/// Schedule a card, then persist both the new card and its review log
+pub fn answer(
+    fsrs: &FSRS,
+    card: KanjiSrs,
+    rating: Rating,
+    now: DateTime<Utc>,
+    duration: Duration,
+) -> Result<KanjiSrs, CliError> {
+    let (card, log) = schedule(fsrs, card, rating, now)?;
+    card_to_db(card.clone())?;
+    log_review(&KanjiReviewLog {
+        kanji: card.kanji,
+        log,
+        state_after: card.card.state,
+        duration: duration.as_millis() as u64,
+    })?;
+    Ok(card)
+}
+
+/// Human readable time until a card is next due
+pub fn format_interval(now: DateTime<Utc>, due: DateTime<Utc>) -> String {
+    let minutes = (due - now).num_minutes().max(0);
+    if minutes < 60 {
+        format!("{}m", minutes)
+    } else if minutes < 60 * 24 {
+        format!("{}h", minutes / 60)
+    } else if minutes < 60 * 24 * 365 {
+        format!("{}d", minutes / (60 * 24))
+    } else {
+        format!("{:.1}y", minutes as f64 / (60.0 * 24.0 * 365.0))
+    }
+}
+
+pub trait CanHaveReview {
+    async fn review_session(&mut self, cards: Vec<KanjiSrs>) -> Result<usize, CliError>;
+}
+
+impl CanHaveReview for Tui {
+    /// Review each card in turn, writing every answer back to the srs table.
+    /// Returns the number of cards that were answered. The terminal is
+    /// restored even when the session ends with an error.
+    async fn review_session(&mut self, cards: Vec<KanjiSrs>) -> Result<usize, CliError> {
+        let answered = review_cards(self, &cards);
+        crate::tui::restore()?;
+        answered
+    }
+}
+
+fn review_cards(tui: &mut Tui, cards: &[KanjiSrs]) -> Result<usize, CliError> {
+    let fsrs = scheduler()?;
+    let mut index = 0;
+    let mut revealed = false;
+    let mut shown_at = Instant::now();
+    let mut info: Option<(usize, Option<KanjiInfo>)> = None;
+
+    while index < cards.len() {
+        let card = &cards[index];
+        if info.as_ref().map_or(true, |(i, _)| *i != index) {
+            info = Some((index, get_kanji_info(card.kanji)?));
+        }
+        let now = Utc::now();
+        let outcomes = preview(&fsrs, card, now);
+
+        let title = Title::from(format!(" Review {}/{} ", index + 1, cards.len()).bold());
+        let instructions = if revealed {
+            let mut spans = vec![];
+            for (i, rating) in RATINGS.iter().enumerate() {
+                let interval = outcomes
+                    .get(rating)
+                    .map(|info| format_interval(now, info.card.due))
+                    .unwrap_or_default();
+                spans.push(format!(" {} ({}) ", rating_name(*rating), interval).into());
+                spans.push(format!("<{}>", i + 1).blue().bold());
+            }
+            spans.push(" Quit ".into());
+            spans.push("<q>".blue().bold());
+            Title::from(Line::from(spans))
+        } else {
+            Title::from(Line::from(vec![
+                " Show Answer ".into(),
+                "<Space>".blue().bold(),
+                " Quit ".into(),
+                "<q>".blue().bold(),
+            ]))
+        };
+        let block = Block::new()
+            .borders(Borders::NONE)
+            .title(title.alignment(Alignment::Center))
+            .title(
+                instructions
+                    .alignment(Alignment::Center)
+                    .position(Position::Bottom),
+            )
+            .fg(tailwind::SLATE.c200)
+            .bg(tailwind::SLATE.c950);
+
+        let mut details = Text::default();
+        if revealed {
+            if let Some((_, Some(info))) = &info {
+                details.push_line(Line::from(info.meanings.join(", ")).bold());
+                details.push_line(format!(
+                    "{}  {}",
+                    info.on_readings.join("、"),
+                    info.kun_readings.join("、")
+                ));
+                details.push_line("");
+            }
+            details.push_line(format!("State: {:?}", card.card.state));
+            details.push_line(format!(
+                "Reviews: {}  Lapses: {}",
+                card.card.reps, card.card.lapses
+            ));
+            details.push_line(format!(
+                "Stability: {:.2}  Difficulty: {:.2}",
+                card.card.stability, card.card.difficulty
+            ));
+        }
+
+        tui.draw(|f| {
+            let inner_area = block.inner(f.size());
+            let layout = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints(vec![
+                    Constraint::Percentage(40),
+                    Constraint::Length(3),
+                    Constraint::Min(0),
+                ])
+                .split(inner_area);
+            let kanji = Paragraph::new(card.kanji.to_string())
+                .alignment(Alignment::Center)
+                .style(Style::default().add_modifier(Modifier::BOLD));
+            let details = Paragraph::new(details).alignment(Alignment::Center);
+            f.render_widget(block, f.size());
+            f.render_widget(kanji, layout[1]);
+            f.render_widget(details, layout[2]);
+        })?;
+
+        if event::poll(Duration::from_millis(16))? {
+            if let event::Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                match key.code {
+                    KeyCode::Char('q') => break,
+                    KeyCode::Char(' ') | KeyCode::Enter => revealed = true,
+                    KeyCode::Char(c) if revealed => {
+                        if let Some(rating) = rating_from_key(c) {
+                            answer(&fsrs, card.clone(), rating, Utc::now(), shown_at.elapsed())?;
+                            revealed = false;
+                            shown_at = Instant::now();
+                            index += 1;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(index)
+}
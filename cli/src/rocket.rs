@@ -1,18 +1,247 @@
-use rocket::{get, routes, Rocket};
-use rocket::fs::{FileServer, relative};
+use std::sync::Arc;
+use std::time::Instant;
 
+use chrono::Utc;
+use rocket::fs::{relative, FileServer};
+use rocket::futures::{SinkExt, StreamExt};
+use rocket::{get, routes, Rocket, State};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::config::read_config;
+use crate::db::{due_cards, get_kanji_info, rating_from_db, record_exposure, KanjiSrs};
+use crate::kanji::KanjiInfo;
+use crate::review::{answer, format_interval, preview, rating_name, scheduler, RATINGS};
+use crate::CliError;
 
 pub fn rocket() -> Rocket<rocket::Build> {
-    rocket::build().mount("/rev", FileServer::from(relative!("static"))).mount("/ws", routes![echo_stream])
+    rocket::build()
+        .manage(SharedSession::default())
+        .mount("/rev", FileServer::from(relative!("static")))
+        .mount("/ws", routes![review_channel, hook_channel])
+        .mount("/api/v1", crate::api::routes())
+}
+
+/// The card currently shown to the browser. Kept in managed state rather than
+/// per connection so a reconnecting client is shown the same card again.
+#[derive(Default)]
+pub struct ReviewSession {
+    current: Option<KanjiSrs>,
+    shown_at: Option<Instant>,
+    reviewed: usize,
 }
 
+type SharedSession = Arc<Mutex<ReviewSession>>;
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Card {
+        kanji: char,
+        /// Dictionary entry shown with the answer, if KANJIDIC2 was imported
+        info: Option<KanjiInfo>,
+        state: String,
+        reps: i32,
+        lapses: i32,
+        stability: f64,
+        difficulty: f64,
+        intervals: Vec<RatingInterval>,
+        remaining: usize,
+        reviewed: usize,
+    },
+    Done {
+        reviewed: usize,
+    },
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Serialize, Debug)]
+pub struct RatingInterval {
+    pub rating: u8,
+    pub name: &'static str,
+    pub interval: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Answer the card that is currently shown
+    Rate { kanji: char, rating: u8 },
+    /// Ask for the current card to be sent again
+    Current,
+}
+
+impl ReviewSession {
+    /// Describe the card being reviewed, picking the next due card if the
+    /// previous one was answered (here or from another client)
+    fn current(&mut self) -> Result<ServerMessage, CliError> {
+        let due = due_cards()?;
+        let still_due = self
+            .current
+            .as_ref()
+            .is_some_and(|current| due.iter().any(|card| card.kanji == current.kanji));
+        if !still_due {
+            self.current = due.first().cloned();
+            self.shown_at = Some(Instant::now());
+        }
+        match &self.current {
+            Some(card) => {
+                let now = Utc::now();
+                let outcomes = preview(&scheduler()?, card, now);
+                Ok(ServerMessage::Card {
+                    kanji: card.kanji,
+                    info: get_kanji_info(card.kanji)?,
+                    state: format!("{:?}", card.card.state),
+                    reps: card.card.reps,
+                    lapses: card.card.lapses,
+                    stability: card.card.stability,
+                    difficulty: card.card.difficulty,
+                    intervals: RATINGS
+                        .iter()
+                        .map(|rating| RatingInterval {
+                            rating: *rating as u8,
+                            name: rating_name(*rating),
+                            interval: outcomes
+                                .get(rating)
+                                .map(|info| format_interval(now, info.card.due))
+                                .unwrap_or_default(),
+                        })
+                        .collect(),
+                    remaining: due.len(),
+                    reviewed: self.reviewed,
+                })
+            }
+            None => Ok(ServerMessage::Done {
+                reviewed: self.reviewed,
+            }),
+        }
+    }
 
-#[get("/echo?stream")]
-fn echo_stream(ws: ws::WebSocket) -> ws::Stream!['static] {
-    ws::Stream! { ws =>
-        for await message in ws {
-            yield message?;
+    /// Apply a rating to the current card. Answers for any other card are
+    /// stale (e.g. from a tab that missed an update) and are ignored.
+    fn rate(&mut self, kanji: char, rating: u8) -> Result<(), CliError> {
+        let rating = rating_from_db(rating)?;
+        if let Some(card) = self.current.take() {
+            if card.kanji != kanji {
+                self.current = Some(card);
+                return Ok(());
+            }
+            let duration = self.shown_at.map(|t| t.elapsed()).unwrap_or_default();
+            answer(&scheduler()?, card, rating, Utc::now(), duration)?;
+            self.reviewed += 1;
         }
+        Ok(())
     }
+
+    fn handle(&mut self, message: &str) -> ServerMessage {
+        let result = serde_json::from_str::<ClientMessage>(message)
+            .map_err(CliError::from)
+            .and_then(|message| match message {
+                ClientMessage::Rate { kanji, rating } => self.rate(kanji, rating),
+                ClientMessage::Current => Ok(()),
+            })
+            .and_then(|_| self.current());
+        result.unwrap_or_else(|err| ServerMessage::Error {
+            message: match err {
+                CliError::Custom(message) => message,
+                err => format!("{:?}", err),
+            },
+        })
+    }
+}
+
+/// Handle a client message on a blocking thread, as answering reads and
+/// writes SQLite. The session stays locked until the reply is ready, so
+/// clients are served one at a time.
+async fn handle_blocking(session: &SharedSession, message: String) -> ServerMessage {
+    let mut session = session.clone().lock_owned().await;
+    tokio::task::spawn_blocking(move || session.handle(&message))
+        .await
+        .unwrap_or_else(|err| ServerMessage::Error {
+            message: err.to_string(),
+        })
 }
 
+fn to_ws(message: ServerMessage) -> ws::Message {
+    ws::Message::Text(serde_json::to_string(&message).unwrap_or_default())
+}
+
+#[get("/review")]
+fn review_channel(ws: ws::WebSocket, session: &State<SharedSession>) -> ws::Channel<'static> {
+    let session = session.inner().clone();
+    ws.channel(move |mut stream| {
+        Box::pin(async move {
+            let current = handle_blocking(&session, r#"{"type":"current"}"#.to_string()).await;
+            stream.send(to_ws(current)).await?;
+            while let Some(message) = stream.next().await {
+                if let ws::Message::Text(text) = message? {
+                    let reply = handle_blocking(&session, text).await;
+                    stream.send(to_ws(reply)).await?;
+                }
+            }
+            Ok(())
+        })
+    })
+}
+
+/// Text from a texthooker. Clipboard inserters send each line as plain text;
+/// some tools wrap it in a JSON object instead.
+fn hook_text(message: &str) -> String {
+    #[derive(Deserialize)]
+    struct HookMessage {
+        #[serde(alias = "text")]
+        sentence: String,
+    }
+    match serde_json::from_str::<HookMessage>(message) {
+        Ok(message) => message.sentence,
+        Err(_) => message.to_string(),
+    }
+}
+
+/// Record the kanji of every line a texthooker sends. Nothing is sent back,
+/// as hookers don't expect replies. Database work runs on a blocking thread.
+#[get("/hook")]
+fn hook_channel(ws: ws::WebSocket) -> ws::Channel<'static> {
+    ws.channel(move |mut stream| {
+        Box::pin(async move {
+            let promote_after = tokio::task::spawn_blocking(read_config)
+                .await
+                .ok()
+                .and_then(Result::ok)
+                .map(|config| config.exposure)
+                .unwrap_or_default()
+                .promote_after;
+            while let Some(message) = stream.next().await {
+                let ws::Message::Text(text) = message? else {
+                    continue;
+                };
+                let lines: Vec<String> = hook_text(&text)
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                if lines.is_empty() {
+                    continue;
+                }
+                let seen_at = Utc::now().timestamp();
+                let recorded = tokio::task::spawn_blocking(move || {
+                    record_exposure(&lines, seen_at, promote_after)
+                })
+                .await;
+                match recorded {
+                    Ok(Ok(promoted)) if !promoted.is_empty() => {
+                        let promoted: String = promoted.into_iter().collect();
+                        println!("Promoted to Seen: {}", promoted);
+                    }
+                    Ok(Ok(_)) => {}
+                    Ok(Err(err)) => eprintln!("{}", err),
+                    Err(err) => eprintln!("{}", err),
+                }
+            }
+            Ok(())
+        })
+    })
+}
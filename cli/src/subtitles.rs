@@ -0,0 +1,147 @@
+use std::path::Path;
+
+/// Subtitle formats that can be read for their dialogue
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubtitleFormat {
+    Srt,
+    Ass,
+    Vtt,
+}
+
+impl SubtitleFormat {
+    pub fn from_path(path: &Path) -> Option<SubtitleFormat> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "srt" => Some(SubtitleFormat::Srt),
+            "ass" | "ssa" => Some(SubtitleFormat::Ass),
+            "vtt" => Some(SubtitleFormat::Vtt),
+            _ => None,
+        }
+    }
+}
+
+/// The dialogue of a subtitle file, one line per cue, without timings or
+/// styling
+pub fn parse_subtitles(format: SubtitleFormat, text: &str) -> Vec<String> {
+    let text = text.trim_start_matches('\u{feff}');
+    let lines = match format {
+        SubtitleFormat::Srt => parse_srt(text),
+        SubtitleFormat::Ass => parse_ass(text),
+        SubtitleFormat::Vtt => parse_vtt(text),
+    };
+    lines
+        .into_iter()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+/// Numbered cues of a timing line followed by text, separated by blank lines
+fn parse_srt(text: &str) -> Vec<String> {
+    let mut cues = vec![];
+    for block in blocks(text) {
+        let mut lines = block.iter().skip_while(|line| !line.contains("-->"));
+        if lines.next().is_none() {
+            continue;
+        }
+        let cue: Vec<&str> = lines.copied().collect();
+        cues.push(strip_markup(&cue.join("\n")));
+    }
+    cues
+}
+
+/// Like SRT, after a WEBVTT header and with NOTE, STYLE and REGION blocks
+fn parse_vtt(text: &str) -> Vec<String> {
+    let mut cues = vec![];
+    for block in blocks(text) {
+        let first = block.first().copied().unwrap_or_default();
+        if ["WEBVTT", "NOTE", "STYLE", "REGION"]
+            .iter()
+            .any(|keyword| first.starts_with(keyword))
+        {
+            continue;
+        }
+        let mut lines = block.iter().skip_while(|line| !line.contains("-->"));
+        if lines.next().is_none() {
+            continue;
+        }
+        let cue: Vec<&str> = lines.copied().collect();
+        cues.push(strip_markup(&cue.join("\n")));
+    }
+    cues
+}
+
+/// Dialogue lines of the [Events] section, whose Text column is last and
+/// may itself contain commas
+fn parse_ass(text: &str) -> Vec<String> {
+    let mut cues = vec![];
+    let mut in_events = false;
+    // Default column count of the v4+ Format line
+    let mut columns = 10;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_events = line.eq_ignore_ascii_case("[events]");
+            continue;
+        }
+        if !in_events {
+            continue;
+        }
+        if let Some(format) = line.strip_prefix("Format:") {
+            columns = format.split(',').count();
+        } else if let Some(dialogue) = line.strip_prefix("Dialogue:") {
+            if let Some(text) = dialogue.splitn(columns, ',').nth(columns - 1) {
+                let text = text
+                    .replace("\\N", "\n")
+                    .replace("\\n", "\n")
+                    .replace("\\h", " ");
+                cues.push(strip_markup(&text));
+            }
+        }
+    }
+    cues
+}
+
+/// Groups of non-blank lines
+fn blocks(text: &str) -> Vec<Vec<&str>> {
+    let mut blocks = vec![];
+    let mut block = vec![];
+    for line in text.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            if !block.is_empty() {
+                blocks.push(std::mem::take(&mut block));
+            }
+        } else {
+            block.push(line);
+        }
+    }
+    if !block.is_empty() {
+        blocks.push(block);
+    }
+    blocks
+}
+
+/// Remove HTML-style tags, ruby readings and ASS `{\...}` override blocks
+fn strip_markup(text: &str) -> String {
+    let text = remove_between(text, "<rt>", "</rt>");
+    let text = remove_between(&text, "<rp>", "</rp>");
+    let text = remove_between(&text, "<", ">");
+    remove_between(&text, "{", "}")
+}
+
+fn remove_between(text: &str, open: &str, close: &str) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(open) {
+        out.push_str(&rest[..start]);
+        match rest[start + open.len()..].find(close) {
+            Some(end) => rest = &rest[start + open.len() + end + close.len()..],
+            None => {
+                rest = &rest[start..];
+                break;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
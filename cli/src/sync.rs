@@ -0,0 +1,309 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use fsrs::{Card, Rating, FSRS};
+use rusqlite::{Connection, TransactionBehavior};
+use serde::Serialize;
+
+use crate::ankiconnect::{
+    deck_query, find_cards, get_cards, get_cards_with_reviews, AnkiReview, CardContent,
+};
+use crate::apkg::Collection;
+use crate::config::DeckConfig;
+use crate::db::{
+    connect, dictionary_readings, skipped_cards, sync_checkpoint, words_by_card, write_sync,
+    SkippedCard, SyncChanges, Word,
+};
+use crate::fields::{parse_field, DictionaryReadings};
+use crate::kanji::{recommended_level, to_hiragana};
+use crate::review::scheduler;
+use crate::CliError;
+
+#[derive(Serialize, Debug, Default)]
+pub struct SyncReport {
+    pub deck: String,
+    /// New or changed cards that were synced
+    pub synced: usize,
+    /// Cards without the configured word field
+    pub skipped: usize,
+    /// Source cards deleted from the deck since the last sync
+    pub removed: usize,
+    /// Kanji whose level was recomputed
+    pub kanji: usize,
+    /// Kanji whose FSRS state was seeded from Anki review history
+    pub seeded: usize,
+    /// Kanji left without any source card
+    pub orphaned: usize,
+    /// Note types whose cards lack their word field, with the fields they do have
+    pub unmapped: BTreeMap<String, Vec<String>>,
+}
+
+/// Sync a deck through AnkiConnect. After the first sync only cards that
+/// are new to the deck, or whose note was edited or that were reviewed since
+/// the deck's checkpoint, are fetched; cards gone from the deck's id list are
+/// removed. Anki's search finds these changes directly, whereas comparing
+/// modification times would mean fetching one for every card in the deck,
+/// and a card's time doesn't change when only its note is edited. One
+/// connection is used for the whole sync.
+pub async fn sync_deck(deck: &DeckConfig) -> Result<SyncReport, CliError> {
+    let now = Utc::now().timestamp();
+    let mut connection = connect()?;
+    let known = known_mod_times(&connection, &deck.name)?;
+    let checkpoint = sync_checkpoint(&connection, &deck.name)?;
+    let ids = get_cards(&deck.name).await?;
+    let fetch = match checkpoint {
+        Some(checkpoint) if checkpoint.field == field_mapping(deck)? => {
+            let days = (now - checkpoint.synced_at).max(0) / 86400 + 1;
+            let query = format!(
+                "{} (edited:{} OR rated:{})",
+                deck_query(&deck.name),
+                days,
+                days
+            );
+            let changed: HashSet<u64> = find_cards(query).await?.into_iter().collect();
+            ids.iter()
+                .copied()
+                .filter(|id| changed.contains(id) || !known.contains_key(id))
+                .collect()
+        }
+        // First sync, or the field mapping changed, so every card is re-read
+        _ => ids.clone(),
+    };
+    let cards = get_cards_with_reviews(&fetch).await?;
+    sync_cards(&mut connection, deck, &ids, cards, now)
+}
+
+/// Sync a deck from a collection file, skipping cards whose modification
+/// time matches the stored one
+pub fn sync_collection(collection: &Collection, deck: &DeckConfig) -> Result<SyncReport, CliError> {
+    let now = Utc::now().timestamp();
+    let mut connection = connect()?;
+    let known = known_mod_times(&connection, &deck.name)?;
+    let cards = collection.deck_cards(&deck.name);
+    let ids: Vec<u64> = cards.iter().map(|card| card.card_id).collect();
+    let mapping = field_mapping(deck)?;
+    let incremental = sync_checkpoint(&connection, &deck.name)?
+        .is_some_and(|checkpoint| checkpoint.field == mapping);
+    let cards = cards
+        .into_iter()
+        .filter(|card| !incremental || known.get(&card.card_id) != Some(&card.modified))
+        .collect();
+    sync_cards(&mut connection, deck, &ids, cards, now)
+}
+
+/// Modification times of the stored words and skipped cards of a deck
+fn known_mod_times(connection: &Connection, deck: &str) -> Result<HashMap<u64, i64>, CliError> {
+    let mut known: HashMap<u64, i64> = words_by_card(connection)?
+        .into_values()
+        .map(|word| (word.card_id, word.modified))
+        .collect();
+    known.extend(
+        skipped_cards(connection, deck)?
+            .into_iter()
+            .map(|card| (card.card_id, card.modified)),
+    );
+    Ok(known)
+}
+
+/// The deck's word fields as stored in its checkpoint, so a sync after the
+/// mapping changes re-reads every card
+fn field_mapping(deck: &DeckConfig) -> Result<String, CliError> {
+    Ok(serde_json::to_string(&(
+        &deck.word_field,
+        &deck.note_fields,
+    ))?)
+}
+
+/// Replay a card's Anki review history through FSRS. Returns None for cards
+/// that were never answered.
+pub fn seed_card(fsrs: &FSRS, reviews: &[AnkiReview]) -> Option<Card> {
+    let mut card: Option<Card> = None;
+    for review in reviews {
+        let rating = match review.ease {
+            1 => Rating::Again,
+            2 => Rating::Hard,
+            3 => Rating::Good,
+            4 => Rating::Easy,
+            // Manual reschedules aren't answers
+            _ => continue,
+        };
+        let Some(now) = DateTime::from_timestamp_millis(review.id) else {
+            continue;
+        };
+        let current = card.take().unwrap_or_else(Card::new);
+        card = Some(match fsrs.repeat(current.clone(), now).remove(&rating) {
+            Some(info) => info.card,
+            None => current,
+        });
+    }
+    card
+}
+
+/// Store the effect of the fetched cards on a deck. The stored cards are read
+/// and the changes written in one transaction, so they are planned against
+/// the same state they are applied to. It takes the write lock up front, as
+/// another writer in between would otherwise fail the transaction's first
+/// write.
+pub fn sync_cards(
+    connection: &mut Connection,
+    deck: &DeckConfig,
+    ids: &[u64],
+    cards: Vec<CardContent>,
+    synced_at: i64,
+) -> Result<SyncReport, CliError> {
+    let fsrs = scheduler()?;
+    let tx = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let (mut changes, mut report) = plan_sync(
+        &fsrs,
+        &dictionary_readings(&tx)?,
+        deck,
+        words_by_card(&tx)?,
+        ids,
+        cards,
+    );
+    changes.field = field_mapping(deck)?;
+    changes.synced_at = synced_at;
+    let current: HashSet<u64> = ids.iter().copied().collect();
+    let (kept, deleted): (Vec<SkippedCard>, Vec<SkippedCard>) = skipped_cards(&tx, &deck.name)?
+        .into_iter()
+        .partition(|card| current.contains(&card.card_id));
+    // Forget skipped cards that were deleted from the deck
+    changes
+        .removed
+        .extend(deleted.into_iter().map(|card| card.card_id));
+    write_sync(&tx, &changes)?;
+    tx.commit()?;
+    // Skipped cards from earlier syncs aren't fetched again, but their note
+    // types still need a word field
+    let synced: HashSet<u64> = changes.cards.iter().map(|card| card.card_id).collect();
+    for card in kept {
+        if !synced.contains(&card.card_id) {
+            report.unmapped.entry(card.model).or_insert(card.fields);
+        }
+    }
+    Ok(report)
+}
+
+/// Work out the effect of the fetched cards on a deck. `ids` are every card
+/// currently in the deck, so stored cards missing from it are removed.
+/// Levels are recomputed from all remaining source cards for each kanji the
+/// changes touch, so they can go down as well as up. A kanji in several words
+/// takes the most stable seeded state among them.
+pub fn plan_sync(
+    fsrs: &FSRS,
+    dictionary: &DictionaryReadings,
+    deck_config: &DeckConfig,
+    stored: HashMap<u64, Word>,
+    ids: &[u64],
+    cards: Vec<CardContent>,
+) -> (SyncChanges, SyncReport) {
+    let deck = deck_config.name.as_str();
+    let current: HashSet<u64> = ids.iter().copied().collect();
+    let mut report = SyncReport {
+        deck: deck.to_string(),
+        ..Default::default()
+    };
+    let mut changes = SyncChanges {
+        deck: deck.to_string(),
+        ..Default::default()
+    };
+    changes.removed = stored
+        .values()
+        .filter(|card| card.deck == deck && !current.contains(&card.card_id))
+        .map(|card| card.card_id)
+        .collect();
+    report.removed = changes.removed.len();
+
+    let mut affected: HashSet<char> = HashSet::new();
+    for card in cards {
+        let word = card.fields.get(deck_config.field_for(&card.model_name));
+        match word {
+            Some(word) => {
+                let seed = seed_card(fsrs, &card.reviews);
+                let parsed = parse_field(&word.value);
+                for kanji in parsed.kanji(dictionary) {
+                    if let Some(reading) = kanji.reading {
+                        let reading = reading.chars().map(to_hiragana).collect();
+                        changes.readings.push((card.card_id, kanji.kanji, reading));
+                    }
+                }
+                let word = Word {
+                    card_id: card.card_id,
+                    note_id: card.note,
+                    deck: deck.to_string(),
+                    word: parsed.text().trim().to_string(),
+                    reading: parsed.reading().map(|r| r.trim().to_string()),
+                    interval: card.interval,
+                    modified: card.modified,
+                };
+                for kanji in word.kanji() {
+                    affected.insert(kanji);
+                    if let Some(seed) = &seed {
+                        let better = changes
+                            .seeds
+                            .get(&kanji)
+                            .map_or(true, |best| seed.stability > best.stability);
+                        if better {
+                            changes.seeds.insert(kanji, seed.clone());
+                        }
+                    }
+                }
+                changes.cards.push(word);
+                report.synced += 1;
+            }
+            None => {
+                if stored.contains_key(&card.card_id) {
+                    changes.removed.push(card.card_id);
+                }
+                let mut fields: Vec<_> = card.fields.iter().collect();
+                fields.sort_by_key(|(_, field)| field.order);
+                let fields: Vec<String> =
+                    fields.into_iter().map(|(name, _)| name.clone()).collect();
+                report
+                    .unmapped
+                    .entry(card.model_name.clone())
+                    .or_insert_with(|| fields.clone());
+                changes.skipped.push(SkippedCard {
+                    card_id: card.card_id,
+                    deck: deck.to_string(),
+                    model: card.model_name,
+                    fields,
+                    modified: card.modified,
+                });
+                report.skipped += 1;
+            }
+        }
+    }
+
+    // Kanji that lose a source card need recomputing too
+    let replaced = changes.cards.iter().map(|card| &card.card_id);
+    for id in changes.removed.iter().chain(replaced) {
+        if let Some(old) = stored.get(id) {
+            affected.extend(old.kanji());
+        }
+    }
+
+    let mut sources = stored;
+    for id in &changes.removed {
+        sources.remove(id);
+    }
+    for card in &changes.cards {
+        sources.insert(card.card_id, card.clone());
+    }
+    for source in sources.values() {
+        let level = recommended_level(source.interval);
+        for kanji in source.kanji().filter(|kanji| affected.contains(kanji)) {
+            let best = changes.levels.entry(kanji).or_insert(level);
+            *best = (*best).max(level);
+        }
+    }
+    changes.orphaned = affected
+        .into_iter()
+        .filter(|kanji| !changes.levels.contains_key(kanji))
+        .collect();
+
+    report.kanji = changes.levels.len();
+    report.seeded = changes.seeds.len();
+    report.orphaned = changes.orphaned.len();
+    (changes, report)
+}
@@ -1,4 +1,7 @@
 use super::*;
+use crate::db::KanjiSrs;
+use crate::kanji::is_kanji;
+use fsrs::Card;
 
 #[test]
 fn test_is_kanji() {
@@ -8,4 +11,677 @@ fn test_is_kanji() {
     assert!(!is_kanji('に'));
     assert!(!is_kanji('h'));
     assert!(!is_kanji('1'))
-} 
\ No newline at end of file
+}
+
+#[test]
+fn test_review_schedule() {
+    let now = chrono::Utc::now();
+    let card = KanjiSrs {
+        kanji: '本',
+        card: Card::new(),
+    };
+    let (reviewed, log) = review::schedule(
+        &fsrs::FSRS::new(fsrs::Parameters::default()),
+        card,
+        fsrs::Rating::Good,
+        now,
+    )
+    .unwrap();
+    assert_eq!(reviewed.kanji, '本');
+    assert_eq!(log.rating, fsrs::Rating::Good);
+    assert_eq!(log.state, fsrs::State::New);
+    assert_eq!(reviewed.card.reps, 1);
+    assert!(reviewed.card.due > now);
+}
+
+#[test]
+fn test_retrievability() {
+    assert!((optimize::retrievability(0.0, 10.0) - 1.0).abs() < 1e-9);
+    assert!((optimize::retrievability(10.0, 10.0) - 0.9).abs() < 1e-9);
+    assert!(optimize::retrievability(100.0, 10.0) < 0.9);
+}
+
+#[test]
+fn test_parse_kanjidic() {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<kanjidic2>
+<character>
+<literal>亜</literal>
+<misc>
+<grade>8</grade>
+<stroke_count>7</stroke_count>
+<stroke_count>8</stroke_count>
+<freq>1509</freq>
+<jlpt>1</jlpt>
+</misc>
+<reading_meaning>
+<rmgroup>
+<reading r_type="pinyin">ya4</reading>
+<reading r_type="ja_on">ア</reading>
+<reading r_type="ja_kun">つ.ぐ</reading>
+<meaning>Asia</meaning>
+<meaning m_lang="fr">Asie</meaning>
+</rmgroup>
+</reading_meaning>
+</character>
+</kanjidic2>"#;
+    let entries = kanjidic::parse_kanjidic(xml.as_bytes()).unwrap();
+    assert_eq!(entries.len(), 1);
+    let info = &entries[0];
+    assert_eq!(info.kanji, '亜');
+    assert_eq!(info.stroke_count, Some(7));
+    assert_eq!(info.grade, Some(8));
+    assert_eq!(info.jlpt, Some(1));
+    assert_eq!(info.frequency, Some(1509));
+    assert_eq!(info.on_readings, vec!["ア"]);
+    assert_eq!(info.kun_readings, vec!["つ.ぐ"]);
+    assert_eq!(info.meanings, vec!["Asia"]);
+}
+
+#[test]
+fn test_parse_radical_files() {
+    let krad = "# comment\n亜 : ｜ 一 口\n";
+    let parsed = radicals::parse_kradfile(krad);
+    assert_eq!(parsed, vec![('亜', vec!['｜', '一', '口'])]);
+
+    let radk = "# comment\n$ 一 1\n亜唖\n娃\n$ 口 3 js01\n亜\n";
+    let parsed = radicals::parse_radkfile(radk);
+    assert_eq!(parsed.len(), 2);
+    assert_eq!(parsed[0].radical, '一');
+    assert_eq!(parsed[0].strokes, 1);
+    assert_eq!(parsed[0].kanji, vec!['亜', '唖', '娃']);
+    assert_eq!(parsed[1].radical, '口');
+    assert_eq!(parsed[1].kanji, vec!['亜']);
+}
+
+#[test]
+fn test_kanjivg_strokes() {
+    use std::path::Path;
+    assert_eq!(
+        kanjivg::file_kanji(Path::new("kanji/04e00.svg")),
+        Some('一')
+    );
+    assert_eq!(
+        kanjivg::file_kanji(Path::new("kanji/04e00-Kaisho.svg")),
+        None
+    );
+    assert_eq!(kanjivg::file_kanji(Path::new("kanji/00041.svg")), None);
+
+    let svg = concat!(
+        r#"<svg><g id="kvg:04e00">"#,
+        r#"<path id="kvg:04e00-s1" d="M11,54.25c3.19,0.62,6.25,0.75,9.73,0.5"/>"#,
+        r#"</g></svg>"#
+    );
+    let strokes = kanjivg::parse_strokes(svg).unwrap();
+    assert_eq!(strokes, vec!["M11,54.25c3.19,0.62,6.25,0.75,9.73,0.5"]);
+
+    let points = kanjivg::stroke_points(&strokes[0]);
+    assert_eq!(points.first(), Some(&(11.0, 54.25)));
+    let last = points.last().unwrap();
+    assert!((last.0 - 20.73).abs() < 1e-9 && (last.1 - 54.75).abs() < 1e-9);
+
+    // Numbers without separators
+    let points = kanjivg::stroke_points("M1.5.5l-1-1");
+    assert_eq!(points, vec![(1.5, 0.5), (0.5, -0.5)]);
+}
+
+#[test]
+fn test_knowledge_levels() {
+    use crate::kanji::{recommended_level, Knowledge};
+    assert_eq!(recommended_level(0), Knowledge::None);
+    assert_eq!(recommended_level(10), Knowledge::Seen);
+    assert_eq!(recommended_level(60), Knowledge::Recognize);
+    assert_eq!(recommended_level(365), Knowledge::Familiar);
+    assert_eq!(Knowledge::from_level(5), Some(Knowledge::Master));
+    assert_eq!(Knowledge::from_level(6), None);
+    assert!(Knowledge::Write > Knowledge::Familiar);
+
+    use ::rocket::form::{FromFormField, ValueField};
+    let parse = |value| Knowledge::from_value(ValueField::from_value(value));
+    assert_eq!(parse("seen").unwrap(), Knowledge::Seen);
+    assert_eq!(parse("Master").unwrap(), Knowledge::Master);
+    assert!(parse("3").is_err());
+}
+
+#[test]
+fn test_migrate_legacy_kanji_table() {
+    let mut connection = rusqlite::Connection::open_in_memory().unwrap();
+    connection
+        .execute_batch(
+            "CREATE TABLE kanji (kanji TEXT NOT NULL);
+            INSERT INTO kanji (kanji) VALUES ('本'), ('本'), ('日');",
+        )
+        .unwrap();
+    migrate::apply(&mut connection).unwrap();
+    assert!(migrate::user_version(&connection).unwrap() > 0);
+
+    let count: usize = connection
+        .query_row("SELECT COUNT(*) FROM kanji WHERE level = 0", [], |row| {
+            row.get(0)
+        })
+        .unwrap();
+    assert_eq!(count, 2);
+    // The rebuilt table has a primary key, so upserts work
+    connection
+        .execute(
+            "INSERT INTO kanji (kanji, level) VALUES ('本', 3)
+            ON CONFLICT(kanji) DO UPDATE SET level = excluded.level",
+            [],
+        )
+        .unwrap();
+
+    // Running again is a no-op
+    migrate::apply(&mut connection).unwrap();
+}
+
+#[test]
+fn test_deck_query_escaping() {
+    assert_eq!(ankiconnect::deck_query("Core 2k"), "deck:\"Core 2k\"");
+    assert_eq!(
+        ankiconnect::deck_query("My \"best\" deck_1*"),
+        "deck:\"My \\\"best\\\" deck\\_1\\*\""
+    );
+}
+
+#[test]
+fn test_read_anki_collection() {
+    let connection = rusqlite::Connection::open_in_memory().unwrap();
+    connection
+        .execute_batch(
+            r#"CREATE TABLE col (decks TEXT, models TEXT);
+            INSERT INTO col VALUES (
+                '{"1": {"name": "Japanese"}, "2": {"name": "Japanese::Core"}, '
+                    || '"3": {"name": "Other"}}',
+                '{"10": {"name": "Vocab", "flds": [{"name": "Word", "ord": 0}, '
+                    || '{"name": "Meaning", "ord": 1}]}}'
+            );
+            CREATE TABLE notes (id INTEGER, mid INTEGER, mod INTEGER, flds TEXT);
+            INSERT INTO notes VALUES (100, 10, 5, '日本' || char(31) || 'Japan'),
+                (101, 10, 5, '本' || char(31) || 'book');
+            CREATE TABLE cards (id INTEGER, nid INTEGER, did INTEGER, mod INTEGER, ivl INTEGER);
+            INSERT INTO cards VALUES (1, 100, 2, 9, 30), (2, 101, 3, 1, -600);
+            CREATE TABLE revlog (id INTEGER, cid INTEGER, ease INTEGER);
+            INSERT INTO revlog VALUES (1700000000000, 1, 3), (1700864000000, 1, 3),
+                (1701000000000, 2, 0);"#,
+        )
+        .unwrap();
+    let collection = apkg::Collection::read(&connection).unwrap();
+    assert_eq!(
+        collection.decks(),
+        vec!["Japanese", "Japanese::Core", "Other"]
+    );
+
+    let cards = collection.deck_cards("Japanese");
+    assert_eq!(cards.len(), 1);
+    assert_eq!(cards[0].interval, 30);
+    assert_eq!(cards[0].fields["Word"].value, "日本");
+    assert_eq!(cards[0].fields["Meaning"].order, 1);
+    assert_eq!(cards[0].model_name, "Vocab");
+    assert_eq!(cards[0].reviews.len(), 2);
+
+    let fsrs = fsrs::FSRS::new(fsrs::Parameters::default());
+    let seeded = sync::seed_card(&fsrs, &cards[0].reviews).unwrap();
+    assert_eq!(seeded.reps, 2);
+    assert!(seeded.due > chrono::DateTime::from_timestamp_millis(1700864000000).unwrap());
+    // Manual reschedules alone don't count as a review
+    let manual = [ankiconnect::AnkiReview {
+        id: 1701000000000,
+        ease: 0,
+    }];
+    assert!(sync::seed_card(&fsrs, &manual).is_none());
+}
+
+#[test]
+fn test_plan_incremental_sync() {
+    let source = |card_id, deck: &str, word: &str, interval| db::Word {
+        card_id,
+        note_id: card_id,
+        deck: deck.to_string(),
+        word: word.to_string(),
+        reading: None,
+        interval,
+        modified: 0,
+    };
+    let stored: std::collections::HashMap<u64, db::Word> = [
+        source(1, "Core", "日本", 120),
+        source(2, "Core", "本", 200),
+        source(3, "Core", "猫", 30),
+        source(4, "Other", "日", 10),
+    ]
+    .into_iter()
+    .map(|card| (card.card_id, card))
+    .collect();
+
+    let card = |card_id, model: &str, field: &str, word: &str, interval| ankiconnect::CardContent {
+        card_id,
+        note: card_id,
+        interval,
+        model_name: model.to_string(),
+        modified: 50,
+        fields: [(
+            field.to_string(),
+            ankiconnect::Field {
+                value: word.to_string(),
+                order: 0,
+            },
+        )]
+        .into_iter()
+        .collect(),
+        reviews: vec![],
+    };
+    // Card 1 lapsed, card 3 was deleted and card 2 is unchanged. Cards 5 and
+    // 6 are new, with the word in a note type specific field.
+    let fetched = vec![
+        card(1, "Vocab", "Word", "日本", 1),
+        card(7, "Vocab", "Word", "先生[せんせい]", 0),
+        card(5, "Kanji", "Kanji", "語[ご]", 100),
+        card(6, "Sentence", "Text", "犬", 100),
+    ];
+    let mut deck = config::DeckConfig::new("Core".to_string(), "Word".to_string());
+    deck.note_fields
+        .insert("Kanji".to_string(), "Kanji".to_string());
+    let fsrs = fsrs::FSRS::new(fsrs::Parameters::default());
+    let dictionary: fields::DictionaryReadings = [
+        ('先', vec!["セン".to_string()]),
+        ('生', vec!["セイ".to_string(), "い.きる".to_string()]),
+    ]
+    .into_iter()
+    .collect();
+    let (changes, report) =
+        sync::plan_sync(&fsrs, &dictionary, &deck, stored, &[1, 2, 5, 6, 7], fetched);
+
+    assert_eq!(changes.removed, vec![3]);
+    assert_eq!(report.synced, 3);
+    assert_eq!(changes.levels[&'語'], kanji::recommended_level(100));
+    assert_eq!(report.unmapped["Sentence"], vec!["Text"]);
+    // Remembered so the next incremental sync doesn't fetch it again
+    assert_eq!(changes.skipped.len(), 1);
+    assert_eq!(
+        (changes.skipped[0].card_id, changes.skipped[0].modified),
+        (6, 50)
+    );
+    assert_eq!(
+        changes.readings,
+        vec![
+            (7, '先', "せん".to_string()),
+            (7, '生', "せい".to_string()),
+            (5, '語', "ご".to_string())
+        ]
+    );
+    let word = changes.cards.iter().find(|w| w.card_id == 5).unwrap();
+    assert_eq!(word.word, "語");
+    assert_eq!(word.reading.as_deref(), Some("ご"));
+    // 日 drops to the level of its best remaining card, in the other deck
+    assert_eq!(changes.levels[&'日'], kanji::recommended_level(10));
+    // 本 is still supported by the unchanged card 2
+    assert_eq!(changes.levels[&'本'], kanji::recommended_level(200));
+    assert_eq!(changes.orphaned, vec!['猫']);
+}
+
+#[test]
+fn test_parse_field() {
+    let dictionary: fields::DictionaryReadings = [
+        ('先', vec!["セン".to_string(), "さき".to_string()]),
+        (
+            '生',
+            vec![
+                "セイ".to_string(),
+                "ショウ".to_string(),
+                "い.きる".to_string(),
+            ],
+        ),
+        ('人', vec!["ジン".to_string(), "ひと".to_string()]),
+        ('日', vec!["ニチ".to_string(), "ひ".to_string()]),
+    ]
+    .into_iter()
+    .collect();
+    let kanji = |value: &str| -> Vec<(char, Option<String>)> {
+        fields::parse_field(value)
+            .kanji(&dictionary)
+            .into_iter()
+            .map(|k| (k.kanji, k.reading))
+            .collect()
+    };
+    assert_eq!(kanji("お茶"), vec![('茶', None)]);
+    assert_eq!(kanji("<b>漢字</b>&nbsp;"), vec![('漢', None), ('字', None)]);
+    assert_eq!(
+        kanji("{{c1::猫::animal}}が好き"),
+        vec![('猫', None), ('好', None)]
+    );
+    assert_eq!(kanji("本[sound:hon.mp3]"), vec![('本', None)]);
+
+    // Readings split around okurigana, but stay with the word when they can't be
+    assert_eq!(
+        kanji("食べる[たべる] 日本[にほん]"),
+        vec![('食', Some("た".to_string())), ('日', None), ('本', None)]
+    );
+    // Word-level furigana is split using dictionary readings, allowing rendaku
+    assert_eq!(
+        kanji("先生[せんせい]"),
+        vec![
+            ('先', Some("せん".to_string())),
+            ('生', Some("せい".to_string()))
+        ]
+    );
+    assert_eq!(
+        kanji("人々[ひとびと]"),
+        vec![('人', Some("ひと".to_string()))]
+    );
+    // No split fits without a reading for 本
+    assert_eq!(kanji("日本[にほん]"), vec![('日', None), ('本', None)]);
+    assert_eq!(
+        kanji("取り扱[とりあつか]い"),
+        vec![
+            ('取', Some("と".to_string())),
+            ('扱', Some("あつか".to_string()))
+        ]
+    );
+    assert_eq!(
+        fields::parse_field(" 日本[にほん]語[ご]").segments,
+        vec![
+            fields::Segment {
+                text: "日本".to_string(),
+                reading: Some("にほん".to_string())
+            },
+            fields::Segment {
+                text: "語".to_string(),
+                reading: Some("ご".to_string())
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_reading_matches() {
+    use crate::kanji::reading_matches;
+    assert!(reading_matches("セイ", "せい"));
+    assert!(reading_matches("い.きる", "い"));
+    assert!(reading_matches("なま", "なま"));
+    assert!(reading_matches("-かた", "がた"));
+    assert!(reading_matches("ガク", "がっ"));
+    assert!(!reading_matches("ショウ", "せい"));
+    assert!(!reading_matches("ガク", "が"));
+}
+
+#[test]
+fn test_kanji_coverage() {
+    let levels: std::collections::HashMap<char, kanji::Knowledge> = [
+        ('日', kanji::Knowledge::Master),
+        ('本', kanji::Knowledge::Seen),
+    ]
+    .into_iter()
+    .collect();
+    let coverage = analyze::KanjiCounts::from_text("日本の日本語、日曜日。鬱").coverage(&levels);
+    assert_eq!(coverage.tokens, 9);
+    assert_eq!(coverage.unique, 5);
+    assert_eq!(coverage.levels[kanji::Knowledge::Master as usize].tokens, 4);
+    assert_eq!(coverage.unknown[0], ('曜', 1));
+    assert_eq!(coverage.unknown.len(), 3);
+    assert!((coverage.comprehension - 5.0 / 9.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_parse_subtitles() {
+    use crate::subtitles::{parse_subtitles, SubtitleFormat};
+    let srt = "1\r\n00:00:01,000 --> 00:00:02,500\r\n<i>今日は</i>{\\an8}\r\n晴れ\r\n\r\n\
+        2\r\n00:00:03,000 --> 00:00:04,000\r\n<font color=\"#fff\">雨</font>\r\n";
+    assert_eq!(
+        parse_subtitles(SubtitleFormat::Srt, srt),
+        ["今日は\n晴れ", "雨"]
+    );
+
+    let ass = "[Script Info]\nTitle: 1話\n\n\
+        [V4+ Styles]\nFormat: Name, Fontname\nStyle: Default,Arial\n\n\
+        [Events]\n\
+        Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n\
+        Dialogue: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,{\\i1}行こう、{\\c&HFFFFFF&}学校へ\\N急げ\n\
+        Comment: 0,0:00:03.00,0:00:04.00,Default,,0,0,0,,注意\n";
+    assert_eq!(
+        parse_subtitles(SubtitleFormat::Ass, ass),
+        ["行こう、学校へ\n急げ"]
+    );
+
+    let vtt = "WEBVTT\n\nNOTE 翻訳者のメモ\n\nSTYLE\n::cue { color: yellow }\n\n\
+        intro\n00:01.000 --> 00:02.000 align:start\n<v 母>ご<c.big>飯</c>よ<00:01.500></v>\n\n\
+        00:03.000 --> 00:04.000\n<ruby>漢字<rt>かんじ</rt></ruby>\n";
+    assert_eq!(
+        parse_subtitles(SubtitleFormat::Vtt, vtt),
+        ["ご飯よ", "漢字"]
+    );
+}
+
+#[test]
+fn test_read_epub_parts() {
+    let container = r#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>"#;
+    assert_eq!(
+        epub::rootfile(container).unwrap().as_deref(),
+        Some("OEBPS/content.opf")
+    );
+
+    let opf = r#"<package xmlns="http://www.idpf.org/2007/opf"><manifest>
+  <item id="css" href="style.css" media-type="text/css"/>
+  <item id="c2" href="text/ch2.xhtml" media-type="application/xhtml+xml"/>
+  <item id="c1" href="text/ch1.xhtml" media-type="application/xhtml+xml"/>
+</manifest><spine><itemref idref="c1"/><itemref idref="c2"/></spine></package>"#;
+    assert_eq!(
+        epub::spine(opf).unwrap(),
+        ["text/ch1.xhtml", "text/ch2.xhtml"]
+    );
+
+    let xhtml = r#"<html xmlns="http://www.w3.org/1999/xhtml">
+<head><title>本</title><style>p{}</style></head>
+<body><h1>第一章</h1><p><ruby>漢字<rp>(</rp><rt>かんじ</rt><rp>)</rp></ruby>を&nbsp;読む</p></body></html>"#;
+    let (title, text) = epub::chapter_text(xhtml).unwrap();
+    assert_eq!(title.as_deref(), Some("第一章"));
+    assert_eq!(
+        text.split_whitespace().collect::<Vec<_>>(),
+        ["第一章", "漢字を", "読む"]
+    );
+
+    let levels: std::collections::HashMap<char, kanji::Knowledge> =
+        [('読', kanji::Knowledge::Familiar)].into_iter().collect();
+    let densities = analyze::new_kanji_density(&["漢字を読む", "漢文を読む", "字"], &levels, 1);
+    assert_eq!(densities.len(), 2);
+    assert_eq!(densities[0].new, [('文', 1)]);
+    assert!((densities[0].per_thousand() - 1000.0 / 3.0).abs() < 1e-9);
+    assert!(densities[1].new.is_empty());
+}
+
+#[test]
+fn test_log_exposure() {
+    let mut connection = rusqlite::Connection::open_in_memory().unwrap();
+    migrate::apply(&mut connection).unwrap();
+    connection
+        .execute_batch(
+            "INSERT INTO kanji (kanji, level) VALUES ('日', 3);
+            INSERT INTO word_kanji (card_id, kanji) VALUES (1, '語');",
+        )
+        .unwrap();
+
+    let promoted = db::log_exposure(&mut connection, "日本語の本", 100, Some(3)).unwrap();
+    assert!(promoted.is_empty());
+    let promoted = db::log_exposure(&mut connection, "日本語", 200, Some(3)).unwrap();
+    // 日 is already known and 語 is in Anki, so only 本 is promoted
+    assert_eq!(promoted, ['本']);
+    assert!(db::log_exposure(&mut connection, "本", 300, Some(3))
+        .unwrap()
+        .is_empty());
+
+    let (count, last_seen): (u32, i64) = connection
+        .query_row(
+            "SELECT count, last_seen FROM kanji_exposure WHERE kanji = '本'",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap();
+    assert_eq!((count, last_seen), (4, 300));
+    let lines: usize = connection
+        .query_row("SELECT COUNT(*) FROM hook_line", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(lines, 3);
+    let level: kanji::Knowledge = connection
+        .query_row("SELECT level FROM kanji WHERE kanji = '日'", [], |row| {
+            row.get(0)
+        })
+        .unwrap();
+    assert_eq!(level, kanji::Knowledge::Familiar);
+}
+
+#[test]
+fn test_recommend_next_kanji() {
+    use std::collections::{HashMap, HashSet};
+    let known: HashSet<char> = ['日', '木'].into_iter().collect();
+    let components: HashMap<char, Vec<char>> = [
+        ('林', vec!['木']),
+        ('明', vec!['日', '月']),
+        ('鬱', vec!['木', '缶', '冖']),
+    ]
+    .into_iter()
+    .collect();
+    let exposure = [
+        ('日', 50),
+        ('林', 10),
+        ('明', 12),
+        ('鬱', 12),
+        ('語', 30),
+        ('本', 6),
+    ];
+    let excluded: HashSet<char> = ['語'].into_iter().collect();
+    let next = analyze::recommend(&exposure, &known, &components, &excluded);
+    let order: Vec<char> = next.iter().map(|r| r.kanji).collect();
+    // 林 is less frequent than 明 but all its components are familiar
+    assert_eq!(order, ['林', '明', '鬱', '本']);
+    assert_eq!(next[1].known_components, ['日']);
+    assert!((next[0].gain - 10.0 / 120.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_recommend_after_exposure() {
+    use std::collections::{HashMap, HashSet};
+    let mut connection = rusqlite::Connection::open_in_memory().unwrap();
+    migrate::apply(&mut connection).unwrap();
+    connection
+        .execute_batch(
+            "INSERT INTO kanji (kanji, level) VALUES ('語', 1);
+            INSERT INTO word_kanji (card_id, kanji) VALUES (1, '語');",
+        )
+        .unwrap();
+    for seen_at in 0..3 {
+        db::log_exposure(&mut connection, "日本語", seen_at, Some(3)).unwrap();
+    }
+    db::store_kanji_level(&connection, '日', kanji::Knowledge::Seen).unwrap();
+
+    let mut stmt = connection
+        .prepare("SELECT kanji, count FROM kanji_exposure")
+        .unwrap();
+    let exposure: Vec<(char, u32)> = stmt
+        .query_map([], |row| {
+            let kanji: String = row.get(0)?;
+            Ok((kanji.chars().next().unwrap(), row.get(1)?))
+        })
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .collect();
+    let learned = db::learned_kanji_from(&connection).unwrap();
+    // 本 was promoted to Seen by exposure alone, so it is still a candidate
+    assert_eq!(learned, ['日', '語'].into_iter().collect());
+    let next = analyze::recommend(&exposure, &learned, &HashMap::new(), &HashSet::new());
+    let order: Vec<char> = next.iter().map(|r| r.kanji).collect();
+    assert_eq!(order, ['本']);
+}
+
+#[test]
+fn test_sync_keeps_manual_levels() {
+    let mut connection = rusqlite::Connection::open_in_memory().unwrap();
+    migrate::apply(&mut connection).unwrap();
+    db::store_kanji_level(&connection, '本', kanji::Knowledge::Master).unwrap();
+    db::store_kanji_level(&connection, '日', kanji::Knowledge::None).unwrap();
+    connection
+        .execute("INSERT INTO kanji (kanji, level) VALUES ('猫', 3)", [])
+        .unwrap();
+
+    let changes = db::SyncChanges {
+        deck: "Core".to_string(),
+        levels: [
+            ('本', kanji::Knowledge::Familiar),
+            ('日', kanji::Knowledge::Seen),
+            ('猫', kanji::Knowledge::Seen),
+        ]
+        .into_iter()
+        .collect(),
+        ..Default::default()
+    };
+    db::write_sync(&connection, &changes).unwrap();
+
+    let level = |kanji: &str| -> kanji::Knowledge {
+        connection
+            .query_row("SELECT level FROM kanji WHERE kanji = ?", [kanji], |row| {
+                row.get(0)
+            })
+            .unwrap()
+    };
+    assert_eq!(level("本"), kanji::Knowledge::Master);
+    // Manual levels are still raised by Anki progress
+    assert_eq!(level("日"), kanji::Knowledge::Seen);
+    assert_eq!(level("猫"), kanji::Knowledge::Seen);
+}
+
+#[test]
+fn test_sync_keeps_stronger_seed() {
+    let mut connection = rusqlite::Connection::open_in_memory().unwrap();
+    migrate::apply(&mut connection).unwrap();
+    let deck = config::DeckConfig::new("Core".to_string(), "Word".to_string());
+    let fsrs = fsrs::FSRS::new(fsrs::Parameters::default());
+    let dictionary = fields::DictionaryReadings::new();
+    let day = 86_400_000;
+    let card = |card_id, word: &str, reviews: Vec<i64>| ankiconnect::CardContent {
+        card_id,
+        note: card_id,
+        interval: 10,
+        model_name: "Vocab".to_string(),
+        modified: 50,
+        fields: [(
+            "Word".to_string(),
+            ankiconnect::Field {
+                value: word.to_string(),
+                order: 0,
+            },
+        )]
+        .into_iter()
+        .collect(),
+        reviews: reviews
+            .into_iter()
+            .map(|id| ankiconnect::AnkiReview { id, ease: 3 })
+            .collect(),
+    };
+    let stability = |connection: &rusqlite::Connection| -> f64 {
+        let blob: Vec<u8> = connection
+            .query_row("SELECT card FROM srs WHERE kanji = '日'", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        bson::from_slice::<Card>(&blob).unwrap().stability
+    };
+
+    // A full sync seeds 日 from a word reviewed over months
+    let start = 1_700_000_000_000;
+    let reviewed = [0, 1, 4, 20, 90].map(|days| start + days * day).to_vec();
+    let fetched = vec![card(1, "日本", reviewed)];
+    let (changes, _) =
+        sync::plan_sync(&fsrs, &dictionary, &deck, Default::default(), &[1], fetched);
+    db::write_sync(&connection, &changes).unwrap();
+    let seeded = stability(&connection);
+
+    // An incremental sync only fetches a new word reviewed once
+    let stored = db::words_by_card(&connection).unwrap();
+    let fetched = vec![card(2, "日", vec![start + 100 * day])];
+    let (changes, _) = sync::plan_sync(&fsrs, &dictionary, &deck, stored, &[1, 2], fetched);
+    assert!(changes.seeds[&'日'].stability < seeded);
+    db::write_sync(&connection, &changes).unwrap();
+    assert_eq!(stability(&connection), seeded);
+}
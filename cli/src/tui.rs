@@ -1,4 +1,7 @@
-use std::io::{self, stdout, Stdout};
+use std::{
+    io::{self, stdout, Stdout},
+    time::Instant,
+};
 
 use ratatui::{
     backend::CrosstermBackend,
@@ -7,23 +10,35 @@ use ratatui::{
         execute,
         terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
     },
-    layout::{Alignment, Constraint, Direction, Layout},
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{
         palette::tailwind::{self, BLACK, GREEN, ORANGE, RED, YELLOW},
-        Modifier, Style, Stylize,
+        Color, Modifier, Style, Stylize,
     },
-    text::Line,
+    symbols::Marker,
+    text::{Line, Span},
     widgets::{
         block::{Position, Title},
-        Block, Borders, List, ListItem, ListState,
+        canvas::{Canvas, Line as CanvasLine},
+        Block, Borders, List, ListItem, ListState, Paragraph, Wrap,
     },
     Terminal,
 };
 
-use crate::{kanji::KanjiEntry, CliError};
+use crate::{
+    db::{get_strokes, known_kanji_with_components},
+    kanji::{KanjiEntry, Knowledge},
+    kanjivg::stroke_points,
+    CliError,
+};
 
 pub type Tui = Terminal<CrosstermBackend<Stdout>>;
 
+/// Width and height of the KanjiVG coordinate space
+const KANJIVG_SIZE: f64 = 109.0;
+/// Time taken to animate a single stroke
+const STROKE_SECONDS: f64 = 0.6;
+
 /// Initialize the terminal
 pub fn init() -> io::Result<Tui> {
     execute!(stdout(), EnterAlternateScreen)?;
@@ -176,6 +191,8 @@ pub struct StatefulList {
     items: Vec<KanjiEntry>,
     page: usize,
     page_count: u16,
+    /// Index of the highlighted kanji in `items`
+    selected: usize,
 }
 
 impl StatefulList {
@@ -185,6 +202,7 @@ impl StatefulList {
             state: ListState::default(),
             items,
             page: 1,
+            selected: 0,
         }
     }
 
@@ -194,6 +212,7 @@ impl StatefulList {
         } else {
             self.page = 1
         }
+        self.selected = (self.page - 1) * 450;
     }
 
     fn previous(&mut self) {
@@ -202,6 +221,21 @@ impl StatefulList {
         } else {
             self.page -= 1
         }
+        self.selected = (self.page - 1) * 450;
+    }
+
+    fn select_next(&mut self) {
+        if self.selected + 1 < self.items.len() {
+            self.selected += 1;
+            self.page = self.selected / 450 + 1;
+        }
+    }
+
+    fn select_previous(&mut self) {
+        if self.selected > 0 {
+            self.selected -= 1;
+            self.page = self.selected / 450 + 1;
+        }
     }
 }
 
@@ -221,6 +255,10 @@ impl CanHaveKanjiList for Tui {
                 "<Left>".blue().bold(),
                 " Next Page ".into(),
                 "<Right>".blue().bold(),
+                " Move ".into(),
+                "<Up/Down>".blue().bold(),
+                " Stroke Order ".into(),
+                "<Enter>".blue().bold(),
                 " Quit ".into(),
                 "<q>".blue().bold(),
             ]));
@@ -240,6 +278,8 @@ impl CanHaveKanjiList for Tui {
             let start = (entries.page - 1) * 450;
             let end = (start + 450).min(entries.items.len());
             let content = &entries.items[start..end];
+            let selected = entries.selected - start;
+            let state = &mut entries.state;
             self.draw(|f| {
                 let inner_area = block.inner(f.size());
                 let layout = Layout::default()
@@ -282,7 +322,12 @@ impl CanHaveKanjiList for Tui {
                                     .add_modifier(Modifier::REVERSED)
                                     .fg(tailwind::BLUE.c300),
                             );
-                    f.render_widget(list, layout[i - 1]);
+                    if (start..end).contains(&selected) {
+                        state.select(Some(selected - start));
+                        f.render_stateful_widget(list, layout[i - 1], state);
+                    } else {
+                        f.render_widget(list, layout[i - 1]);
+                    }
                 }
             })?;
 
@@ -294,6 +339,24 @@ impl CanHaveKanjiList for Tui {
                     if key.kind == KeyEventKind::Press && key.code == KeyCode::Left {
                         entries.previous()
                     }
+                    if key.kind == KeyEventKind::Press && key.code == KeyCode::Down {
+                        entries.select_next()
+                    }
+                    if key.kind == KeyEventKind::Press && key.code == KeyCode::Up {
+                        entries.select_previous()
+                    }
+                    if key.kind == KeyEventKind::Press && key.code == KeyCode::Enter {
+                        let kanji = entries
+                            .items
+                            .get(entries.selected)
+                            .and_then(|entry| entry.kanji.chars().next());
+                        if let Some(kanji) = kanji {
+                            let strokes = get_strokes(kanji)?;
+                            if !strokes.is_empty() {
+                                self.stroke_order(kanji, strokes).await?;
+                            }
+                        }
+                    }
                     if key.kind == KeyEventKind::Press && key.code == KeyCode::Char('q') {
                         is_exit = true;
                         crate::tui::restore()?;
@@ -306,6 +369,257 @@ impl CanHaveKanjiList for Tui {
     }
 }
 
+pub trait CanHaveStrokeOrder {
+    /// Animate the strokes of a kanji. Leaves the terminal initialised so it
+    /// can be opened from other views.
+    async fn stroke_order(&mut self, kanji: char, strokes: Vec<String>) -> Result<(), CliError>;
+}
+
+impl CanHaveStrokeOrder for Tui {
+    async fn stroke_order(&mut self, kanji: char, strokes: Vec<String>) -> Result<(), CliError> {
+        let strokes: Vec<Vec<(f64, f64)>> = strokes.iter().map(|d| stroke_points(d)).collect();
+        // Strokes fully drawn, and progress through the next one (0.0-1.0)
+        let mut drawn = 0;
+        let mut progress = 0.0;
+        let mut playing = true;
+        let mut last_tick = Instant::now();
+        loop {
+            if playing {
+                progress += last_tick.elapsed().as_secs_f64() / STROKE_SECONDS;
+                if progress >= 1.0 {
+                    progress = 0.0;
+                    drawn += 1;
+                    if drawn >= strokes.len() {
+                        drawn = strokes.len();
+                        playing = false;
+                    }
+                }
+            }
+            last_tick = Instant::now();
+
+            let title = Title::from(
+                format!(
+                    " {} - Stroke {}/{} ",
+                    kanji,
+                    drawn.min(strokes.len()),
+                    strokes.len()
+                )
+                .bold(),
+            );
+            let instructions = Title::from(Line::from(vec![
+                " Play/Pause ".into(),
+                "<Space>".blue().bold(),
+                " Step ".into(),
+                "<Left/Right>".blue().bold(),
+                " Restart ".into(),
+                "<r>".blue().bold(),
+                " Back ".into(),
+                "<q>".blue().bold(),
+            ]));
+            let block = Block::new()
+                .title(title.alignment(Alignment::Center))
+                .title(
+                    instructions
+                        .alignment(Alignment::Center)
+                        .position(Position::Bottom),
+                )
+                .fg(tailwind::SLATE.c200)
+                .bg(tailwind::SLATE.c950);
+
+            self.draw(|f| {
+                let inner_area = block.inner(f.size());
+                // Braille cells are roughly twice as tall as they are wide
+                let height = inner_area.height.min(inner_area.width / 2);
+                let area = Rect {
+                    x: inner_area.x + (inner_area.width - height * 2) / 2,
+                    y: inner_area.y + (inner_area.height - height) / 2,
+                    width: height * 2,
+                    height,
+                };
+                let canvas = Canvas::default()
+                    .marker(Marker::Braille)
+                    .x_bounds([0.0, KANJIVG_SIZE])
+                    .y_bounds([0.0, KANJIVG_SIZE])
+                    .paint(|ctx| {
+                        for (i, points) in strokes.iter().enumerate() {
+                            let (color, shown) = if i < drawn {
+                                (tailwind::SLATE.c200, points.len())
+                            } else if i == drawn {
+                                (
+                                    tailwind::RED.c400,
+                                    (points.len() as f64 * progress).ceil() as usize,
+                                )
+                            } else {
+                                (tailwind::SLATE.c700, points.len())
+                            };
+                            for pair in points[..shown.min(points.len())].windows(2) {
+                                ctx.draw(&CanvasLine {
+                                    x1: pair[0].0,
+                                    y1: KANJIVG_SIZE - pair[0].1,
+                                    x2: pair[1].0,
+                                    y2: KANJIVG_SIZE - pair[1].1,
+                                    color,
+                                });
+                            }
+                        }
+                    });
+                f.render_widget(block, f.size());
+                f.render_widget(canvas, area);
+            })?;
+
+            if event::poll(std::time::Duration::from_millis(16))? {
+                if let event::Event::Key(key) = event::read()? {
+                    if key.kind != KeyEventKind::Press {
+                        continue;
+                    }
+                    match key.code {
+                        KeyCode::Char(' ') => {
+                            if drawn >= strokes.len() {
+                                drawn = 0;
+                                progress = 0.0;
+                            }
+                            playing = !playing;
+                        }
+                        KeyCode::Right => {
+                            playing = false;
+                            progress = 0.0;
+                            drawn = (drawn + 1).min(strokes.len());
+                        }
+                        KeyCode::Left => {
+                            playing = false;
+                            progress = 0.0;
+                            drawn = drawn.saturating_sub(1);
+                        }
+                        KeyCode::Char('r') => {
+                            drawn = 0;
+                            progress = 0.0;
+                            playing = true;
+                        }
+                        KeyCode::Char('q') => break,
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub trait CanHaveRadicalSearch {
+    async fn radical_search(&mut self, radicals: Vec<(char, u8)>) -> Result<(), CliError>;
+}
+
+impl CanHaveRadicalSearch for Tui {
+    async fn radical_search(&mut self, radicals: Vec<(char, u8)>) -> Result<(), CliError> {
+        let mut list = SelectionList::new(
+            radicals
+                .iter()
+                .map(|(radical, strokes)| format!("{} {}", radical, strokes))
+                .collect(),
+        );
+        let mut selected: Vec<char> = vec![];
+        let mut matches: Vec<KanjiEntry> = vec![];
+        list.state.select_first();
+        loop {
+            let title = Title::from("Radical Search".bold());
+            let instructions = Title::from(Line::from(vec![
+                format!(" {} matches ", matches.len()).into(),
+                " Toggle ".into(),
+                "<Space>".blue().bold(),
+                " Clear ".into(),
+                "<c>".blue().bold(),
+                " Quit ".into(),
+                "<q>".blue().bold(),
+            ]));
+            let block = Block::new()
+                .title(title.alignment(Alignment::Center))
+                .title(
+                    instructions
+                        .alignment(Alignment::Center)
+                        .position(Position::Bottom),
+                )
+                .fg(tailwind::SLATE.c200)
+                .bg(tailwind::SLATE.c950);
+
+            let items: Vec<ListItem> = radicals
+                .iter()
+                .map(|(radical, strokes)| {
+                    let mark = if selected.contains(radical) {
+                        "[x]"
+                    } else {
+                        "[ ]"
+                    };
+                    ListItem::new(format!("{} {} ({})", mark, radical, strokes))
+                })
+                .collect();
+            let results: Vec<Span> = matches
+                .iter()
+                .map(|entry| {
+                    Span::styled(
+                        format!("{} ", entry.kanji),
+                        (BLACK, level_color(entry.level)),
+                    )
+                })
+                .collect();
+
+            self.draw(|f| {
+                let inner_area = block.inner(f.size());
+                let layout = Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints(vec![Constraint::Length(16), Constraint::Min(0)])
+                    .split(inner_area);
+                let list_widget = List::new(items).highlight_symbol(">>").highlight_style(
+                    Style::default()
+                        .add_modifier(Modifier::BOLD)
+                        .add_modifier(Modifier::REVERSED)
+                        .fg(tailwind::BLUE.c300),
+                );
+                let results = Paragraph::new(Line::from(results)).wrap(Wrap { trim: false });
+                f.render_widget(block, f.size());
+                f.render_stateful_widget(list_widget, layout[0], &mut list.state);
+                f.render_widget(results, layout[1]);
+            })?;
+
+            if event::poll(std::time::Duration::from_millis(16))? {
+                if let event::Event::Key(key) = event::read()? {
+                    if key.kind != KeyEventKind::Press {
+                        continue;
+                    }
+                    match key.code {
+                        KeyCode::Down => list.next(),
+                        KeyCode::Up => list.previous(),
+                        KeyCode::Char(' ') | KeyCode::Enter => {
+                            if let Some(index) = list.state.selected() {
+                                let radical = radicals[index].0;
+                                if let Some(pos) = selected.iter().position(|r| *r == radical) {
+                                    selected.remove(pos);
+                                } else {
+                                    selected.push(radical);
+                                }
+                                matches = if selected.is_empty() {
+                                    vec![]
+                                } else {
+                                    known_kanji_with_components(&selected)?
+                                };
+                            }
+                        }
+                        KeyCode::Char('c') => {
+                            selected.clear();
+                            matches.clear();
+                        }
+                        KeyCode::Char('q') => {
+                            crate::tui::restore()?;
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 fn round_up_to_nearest_10(n: u16) -> u16 {
     (n + 449) / 450 * 450
 }
@@ -316,16 +630,21 @@ pub trait IntoListItem<'a> {
 
 impl<'a> IntoListItem<'a> for KanjiEntry {
     fn to_list_item(&self) -> ListItem<'a> {
-        let bg_color = match self.level {
-            0 => RED.c600,
-            1 => RED.c400,
-            2 => ORANGE.c400,
-            3 => YELLOW.c300,
-            4 => GREEN.c600,
-            _ => RED.c500,
-        };
+        let bg_color = level_color(self.level);
         let line = Line::styled(self.kanji.clone(), (BLACK, bg_color));
 
         ListItem::new(line).bg(bg_color)
     }
 }
+
+/// Background colour used to show a kanji's level
+pub fn level_color(level: Knowledge) -> Color {
+    match level {
+        Knowledge::None => RED.c600,
+        Knowledge::Seen => RED.c400,
+        Knowledge::Recognize => ORANGE.c400,
+        Knowledge::Familiar => YELLOW.c300,
+        Knowledge::Write => GREEN.c600,
+        Knowledge::Master => GREEN.c400,
+    }
+}